@@ -0,0 +1,114 @@
+//! Compiles every shader in `src/shaders` to SPIR-V, plus the variants listed
+//! in `src/variants.rs`, and generates the table `shader::embedded` looks them
+//! up in. Other files there, like `.glsl`, are only included by shaders.
+//!
+//! The compute shaders in `tests/shaders` get a separate table for the tests.
+
+use std::{
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use glsl_to_spirv::ShaderType;
+
+#[allow(dead_code)]
+#[path = "src/preprocess.rs"]
+mod preprocess;
+
+#[path = "src/variants.rs"]
+mod variants;
+
+const SHADER_DIR: &str = "src/shaders";
+
+/// Shaders only the tests dispatch. They are built like the others, but to
+/// a table of their own in `OUT_DIR/tests/shaders.rs` that the library
+/// doesn't embed.
+const TEST_SHADER_DIR: &str = "tests/shaders";
+const TEST_VARIANTS: &[(&str, &[(&str, &str)])] = &[
+    // Inverts alpha along with the color.
+    ("invert.comp", &[("INVERT_ALPHA", "1")]),
+];
+
+fn shader_type(path: &Path) -> Option<ShaderType> {
+    match path.extension()?.to_str()? {
+        "vert" => Some(ShaderType::Vertex),
+        "frag" => Some(ShaderType::Fragment),
+        "comp" => Some(ShaderType::Compute),
+        _ => None,
+    }
+}
+
+/// Preprocesses `path` with `defines` and compiles it to SPIR-V.
+///
+/// Includes are looked up next to the including file, then in `SHADER_DIR`,
+/// like `shader::compile_file` does.
+fn compile(path: &Path, ty: ShaderType, defines: &[(&str, &str)]) -> Vec<u8> {
+    let preprocessor = preprocess::Preprocessor::new().include_dir(SHADER_DIR);
+    let source = defines
+        .iter()
+        .fold(preprocessor, |p, (name, value)| p.define(name, value))
+        .process_file(path)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let mut spirv = Vec::new();
+    glsl_to_spirv::compile(&source.code, ty)
+        .unwrap_or_else(|e| panic!("Can't compile {}:\n{}", path.display(), source.map_log(&e)))
+        .read_to_end(&mut spirv)
+        .unwrap();
+    spirv
+}
+
+/// Compiles every shader in `dir` and the `variants` of them into `spv_dir`,
+/// returning the source of a table `static_name` of their SPIR-V by
+/// `variants::variant_key`.
+fn build_table(
+    dir: &str,
+    variants: &[(&str, &[(&str, &str)])],
+    spv_dir: &Path,
+    static_name: &str,
+) -> String {
+    println!("cargo:rerun-if-changed={}", dir);
+    fs::create_dir_all(spv_dir).unwrap();
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("Can't read shader directory")
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+
+    let mut table = format!("pub static {}: &[(&str, &[u8])] = &[\n", static_name);
+    for path in paths {
+        let ty = match shader_type(&path) {
+            Some(ty) => ty,
+            None => continue,
+        };
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let spv_path = spv_dir.join(format!("{}.spv", name));
+        fs::write(&spv_path, compile(&path, ty, &[])).unwrap();
+        table.push_str(&format!("    ({:?}, include_bytes!({:?})),\n", name, spv_path));
+    }
+    for (i, (name, defines)) in variants.iter().enumerate() {
+        let path = Path::new(dir).join(name);
+        let ty = shader_type(&path).unwrap_or_else(|| panic!("Unknown shader stage of variant {}", name));
+        let spv_path = spv_dir.join(format!("{}.{}.spv", name, i));
+        fs::write(&spv_path, compile(&path, ty, defines)).unwrap();
+        let key = variants::variant_key(name, defines);
+        table.push_str(&format!("    ({:?}, include_bytes!({:?})),\n", key, spv_path));
+    }
+    table.push_str("];\n");
+    table
+}
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed=src/variants.rs");
+
+    let table = build_table(SHADER_DIR, variants::VARIANTS, &out_dir, "SHADERS");
+    fs::write(out_dir.join("shaders.rs"), table).unwrap();
+
+    let test_dir = out_dir.join("tests");
+    let table = build_table(TEST_SHADER_DIR, TEST_VARIANTS, &test_dir, "TEST_SHADERS");
+    fs::write(test_dir.join("shaders.rs"), table).unwrap();
+}
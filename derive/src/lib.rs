@@ -0,0 +1,238 @@
+//! `#[derive(VertexLayout)]` for the `core` crate.
+//!
+//! The input is parsed straight from the token stream, which is enough for
+//! the plain structs vertex types are.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+/// Implements `core::vertex::VertexLayout` for a `#[repr(C)]` struct.
+///
+/// Fields get consecutive shader locations in declaration order, their
+/// formats come from `VertexFormat` and their offsets from `offset_of!`.
+///
+/// Field attributes:
+/// - `#[vertex(format = "Rg16Unorm")]` picks the format instead of the type.
+/// - `#[vertex(skip)]` leaves the field, e.g. padding, out of the layout.
+///
+/// Struct attribute, required:
+/// - `#[vertex(crate = "path")]` names the `core` crate as seen from the
+///   deriving code: `::core` for a plain dependency, the renamed name if it
+///   was renamed in `Cargo.toml`, `crate` inside `core` itself. There is no
+///   default because `::core` would also name the standard library's `core`
+///   in any crate not depending on this one.
+#[proc_macro_derive(VertexLayout, attributes(vertex))]
+pub fn derive_vertex_layout(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    krate: Option<String>,
+    format: Option<String>,
+    skip: bool,
+}
+
+struct Field {
+    /// Name, or index for tuple structs.
+    member: String,
+    ty: String,
+    options: Options,
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter().peekable();
+
+    let options = parse_attributes(&mut tokens)?;
+    skip_visibility(&mut tokens);
+
+    match tokens.next() {
+        Some(TokenTree::Ident(ref ident)) if ident.to_string() == "struct" => {}
+        _ => return Err("VertexLayout can only be derived for structs".into()),
+    }
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        _ => return Err("expected a struct name".into()),
+    };
+
+    let fields = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+            parse_fields(group.stream(), true)?
+        }
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+            parse_fields(group.stream(), false)?
+        }
+        Some(TokenTree::Punct(ref punct)) if punct.as_char() == '<' => {
+            return Err("VertexLayout can't be derived for generic structs".into())
+        }
+        _ => return Err("VertexLayout needs a struct with fields".into()),
+    };
+
+    let krate = options
+        .krate
+        .ok_or("VertexLayout needs #[vertex(crate = \"...\")] naming the path to the core crate, e.g. \"::core\"")?;
+    let mut attributes = String::new();
+    for (location, field) in fields.iter().filter(|field| !field.options.skip).enumerate() {
+        let format = match &field.options.format {
+            Some(format) => format!("{}::vertex::Format::{}", krate, format),
+            None => format!("<{} as {}::vertex::VertexFormat>::FORMAT", field.ty, krate),
+        };
+        attributes.push_str(&format!(
+            "{krate}::vertex::attribute({location}, binding, {format}, ::std::mem::offset_of!({name}, {member})),",
+            krate = krate,
+            location = location,
+            format = format,
+            name = name,
+            member = field.member,
+        ));
+    }
+
+    let output = format!(
+        "impl {krate}::vertex::VertexLayout for {name} {{
+            fn attributes(binding: u32) -> ::std::vec::Vec<{krate}::vertex::AttributeDesc> {{
+                ::std::vec![{attributes}]
+            }}
+        }}",
+        krate = krate,
+        name = name,
+        attributes = attributes,
+    );
+    output.parse().map_err(|e| format!("{:?}", e))
+}
+
+/// Consumes outer attributes, collecting the `vertex(...)` options among them.
+fn parse_attributes<I>(tokens: &mut std::iter::Peekable<I>) -> Result<Options, String>
+where
+    I: Iterator<Item = TokenTree>,
+{
+    let mut options = Options::default();
+    while let Some(TokenTree::Punct(punct)) = tokens.peek() {
+        if punct.as_char() != '#' {
+            break;
+        }
+        tokens.next();
+        let group = match tokens.next() {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => group,
+            _ => return Err("malformed attribute".into()),
+        };
+
+        let mut inner = group.stream().into_iter();
+        match inner.next() {
+            Some(TokenTree::Ident(ref ident)) if ident.to_string() == "vertex" => {}
+            _ => continue,
+        }
+        let args = match inner.next() {
+            Some(TokenTree::Group(args)) if args.delimiter() == Delimiter::Parenthesis => args,
+            _ => return Err("expected #[vertex(...)]".into()),
+        };
+        parse_options(args.stream(), &mut options)?;
+    }
+    Ok(options)
+}
+
+/// Parses `skip` and `key = "value"` pairs separated by commas.
+fn parse_options(stream: TokenStream, options: &mut Options) -> Result<(), String> {
+    let mut tokens = stream.into_iter();
+    while let Some(token) = tokens.next() {
+        let key = match token {
+            TokenTree::Ident(ident) => ident.to_string(),
+            TokenTree::Punct(ref punct) if punct.as_char() == ',' => continue,
+            other => return Err(format!("unexpected `{}` in #[vertex(...)]", other)),
+        };
+
+        if key == "skip" {
+            options.skip = true;
+            continue;
+        }
+
+        match tokens.next() {
+            Some(TokenTree::Punct(ref punct)) if punct.as_char() == '=' => {}
+            _ => return Err(format!("expected `{} = \"...\"`", key)),
+        }
+        let value = match tokens.next() {
+            Some(TokenTree::Literal(literal)) => {
+                let literal = literal.to_string();
+                if literal.len() < 2 || !literal.starts_with('"') || !literal.ends_with('"') {
+                    return Err(format!("the value of `{}` must be a string", key));
+                }
+                literal[1..literal.len() - 1].to_string()
+            }
+            _ => return Err(format!("the value of `{}` must be a string", key)),
+        };
+
+        match key.as_str() {
+            "crate" => options.krate = Some(value),
+            "format" => options.format = Some(value),
+            _ => return Err(format!("unknown vertex option `{}`", key)),
+        }
+    }
+    Ok(())
+}
+
+/// Consumes `pub`, `pub(crate)` and the like.
+fn skip_visibility<I>(tokens: &mut std::iter::Peekable<I>)
+where
+    I: Iterator<Item = TokenTree>,
+{
+    if let Some(TokenTree::Ident(ident)) = tokens.peek() {
+        if ident.to_string() == "pub" {
+            tokens.next();
+            if let Some(TokenTree::Group(group)) = tokens.peek() {
+                if group.delimiter() == Delimiter::Parenthesis {
+                    tokens.next();
+                }
+            }
+        }
+    }
+}
+
+fn parse_fields(stream: TokenStream, named: bool) -> Result<Vec<Field>, String> {
+    let mut fields = Vec::new();
+    let mut tokens = stream.into_iter().peekable();
+
+    while tokens.peek().is_some() {
+        let options = parse_attributes(&mut tokens)?;
+        skip_visibility(&mut tokens);
+
+        let member = if named {
+            let name = match tokens.next() {
+                Some(TokenTree::Ident(ident)) => ident.to_string(),
+                _ => return Err("expected a field name".into()),
+            };
+            match tokens.next() {
+                Some(TokenTree::Punct(ref punct)) if punct.as_char() == ':' => {}
+                _ => return Err(format!("expected `:` after `{}`", name)),
+            }
+            name
+        } else {
+            fields.len().to_string()
+        };
+
+        // Commas inside generic arguments aren't in a group, so track the
+        // angle brackets to find the one ending the field.
+        let mut ty = TokenStream::new();
+        let mut depth = 0;
+        for token in tokens.by_ref() {
+            if let TokenTree::Punct(ref punct) = token {
+                match punct.as_char() {
+                    '<' => depth += 1,
+                    '>' => depth -= 1,
+                    ',' if depth == 0 => break,
+                    _ => {}
+                }
+            }
+            ty.extend(Some(token));
+        }
+
+        fields.push(Field {
+            member,
+            ty: ty.to_string(),
+            options,
+        });
+    }
+    Ok(fields)
+}
@@ -0,0 +1,30 @@
+#!/bin/sh
+# Runs the tests that need a Vulkan adapter (tests/golden.rs and
+# tests/compute.rs, #[ignore]d by default) on Mesa's lavapipe software
+# device, so they behave the same on any machine.
+#
+#   scripts/gpu-tests.sh          check renders against tests/golden
+#   scripts/gpu-tests.sh --bless  write tests/golden/*.png from the renders
+#
+# Blessed references should be looked at before they are committed. Failed
+# checks leave the render and a diff in tests/golden/failures.
+set -eu
+
+cd "$(dirname "$0")/.."
+
+if [ -z "${VK_ICD_FILENAMES:-}" ]; then
+    for icd in /usr/share/vulkan/icd.d/lvp_icd*.json; do
+        [ -e "$icd" ] && VK_ICD_FILENAMES=$icd && break
+    done
+fi
+if [ -z "${VK_ICD_FILENAMES:-}" ]; then
+    echo "lavapipe not found, install mesa-vulkan-drivers or set VK_ICD_FILENAMES" >&2
+    exit 1
+fi
+export VK_ICD_FILENAMES
+
+if [ "${1:-}" = "--bless" ]; then
+    export GOLDEN_BLESS=1
+fi
+
+cargo test --test golden --test compute -- --ignored
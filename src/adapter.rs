@@ -1,11 +1,12 @@
 use hal::{
-    adapter::{Adapter, MemoryType},
+    adapter::{Adapter, AdapterInfo, MemoryType},
     prelude::*,
     Backend,
 };
 
 pub struct AdapterState<B: Backend> {
     pub adapter: Option<Adapter<B>>,
+    pub info: AdapterInfo,
     pub memory_types: Vec<MemoryType>,
     pub limits: hal::Limits,
 }
@@ -27,6 +28,7 @@ impl<B: Backend> AdapterState<B> {
         println!("{:?}", limits);
 
         AdapterState {
+            info: adapter.info.clone(),
             adapter: Some(adapter),
             memory_types,
             limits,
@@ -11,11 +11,6 @@ extern crate gfx_backend_metal as back;
 #[cfg(feature = "vulkan")]
 extern crate gfx_backend_vulkan as back;
 
-use std::{
-    mem::ManuallyDrop,
-    ptr,
-};
-
 use hal::{
     prelude::*,
     Backend,
@@ -23,20 +18,23 @@ use hal::{
 
 use crate::adapter::AdapterState;
 
+/// The backend selected by the enabled cargo features.
+pub type DefaultBackend = back::Backend;
+
+/// `surface` and `window` are `None` for a headless backend.
 pub struct BackendState<B: Backend> {
     instance: Option<B::Instance>,
-    pub surface: ManuallyDrop<B::Surface>,
+    pub surface: Option<B::Surface>,
     pub adapter: AdapterState<B>,
     /// Needs to be kept alive even if its not used directly
     #[allow(dead_code)]
-    pub window: winit::window::Window,
+    pub window: Option<winit::window::Window>,
 }
 
 impl<B: Backend> Drop for BackendState<B> {
     fn drop(&mut self) {
-        if let Some(instance) = &self.instance {
+        if let (Some(instance), Some(surface)) = (&self.instance, self.surface.take()) {
             unsafe {
-                let surface = ManuallyDrop::into_inner(ptr::read(&self.surface));
                 instance.destroy_surface(surface);
             }
         }
@@ -59,7 +57,25 @@ pub fn create_backend(
     BackendState {
         instance: Some(instance),
         adapter: AdapterState::new(&mut adapters),
-        surface: ManuallyDrop::new(surface),
-        window,
+        surface: Some(surface),
+        window: Some(window),
     }
+}
+
+/// Creates a backend without a window or surface, for offscreen rendering.
+///
+/// Returns `None` when no instance can be created or no adapter is found,
+/// e.g. on machines without any Vulkan driver.
+pub fn create_headless_backend() -> Option<BackendState<back::Backend>> {
+    let instance = back::Instance::create("gfx-rs headless", 1).ok()?;
+    let mut adapters = instance.enumerate_adapters();
+    if adapters.is_empty() {
+        return None;
+    }
+    Some(BackendState {
+        instance: Some(instance),
+        adapter: AdapterState::new(&mut adapters),
+        surface: None,
+        window: None,
+    })
 }
\ No newline at end of file
@@ -0,0 +1,201 @@
+use std::ops::Range;
+
+use hal::pso::Primitive;
+
+use crate::pipeline::Vertex;
+
+/// Batches switch to `u32` indices once they hold more vertices than this.
+pub const MAX_U16_VERTICES: u32 = u16::MAX as u32;
+
+/// Upper bound on vertices in one batch, the smallest `maxDrawIndexedIndexValue`
+/// a device may report. A batch that would grow past it is split in two.
+pub const MAX_BATCH_VERTICES: u32 = (1 << 24) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexWidth {
+    U16,
+    U32,
+}
+
+/// One draw call worth of geometry sharing the same key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch<K> {
+    pub key: K,
+    /// Added to every index of the batch when drawing.
+    pub base_vertex: u32,
+    pub vertex_count: u32,
+    pub width: IndexWidth,
+    /// Range into `indices_u16` or `indices_u32`, depending on `width`.
+    pub indices: Range<u32>,
+}
+
+/// Collects submitted geometry into as few batches as possible, merging
+/// consecutive submissions whose keys compare equal.
+#[derive(Debug)]
+pub struct Batcher<K> {
+    vertices: Vec<Vertex>,
+    indices_u16: Vec<u16>,
+    indices_u32: Vec<u32>,
+    batches: Vec<Batch<K>>,
+    split: bool,
+}
+
+impl<K: PartialEq + Copy> Batcher<K> {
+    pub fn new() -> Self {
+        Batcher {
+            vertices: Vec::new(),
+            indices_u16: Vec::new(),
+            indices_u32: Vec::new(),
+            batches: Vec::new(),
+            split: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices_u16.clear();
+        self.indices_u32.clear();
+        self.batches.clear();
+        self.split = false;
+    }
+
+    /// Makes the next `push` start a new batch even if its key matches, for
+    /// when something else is drawn in between.
+    pub fn split(&mut self) {
+        self.split = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    pub fn indices_u16(&self) -> &[u16] {
+        &self.indices_u16
+    }
+
+    pub fn indices_u32(&self) -> &[u32] {
+        &self.indices_u32
+    }
+
+    pub fn batches(&self) -> &[Batch<K>] {
+        &self.batches
+    }
+
+    /// Appends a triangle list. `indices` are relative to `vertices`; without
+    /// them the vertices are drawn in order.
+    ///
+    /// Returns whether anything was added, which it isn't without vertices.
+    pub fn push(&mut self, key: K, vertices: &[Vertex], indices: Option<&[u16]>) -> bool {
+        self.push_indices(key, vertices, indices)
+    }
+
+    /// Like `push`, for geometry with more vertices than `u16` indices reach.
+    /// The indices still go into a `u16` batch when it has room for them.
+    pub fn push_u32(&mut self, key: K, vertices: &[Vertex], indices: &[u32]) -> bool {
+        self.push_indices(key, vertices, Some(indices))
+    }
+
+    fn push_indices<I: Copy + Into<u32>>(&mut self, key: K, vertices: &[Vertex], indices: Option<&[I]>) -> bool {
+        let count = vertices.len() as u32;
+        if count == 0 {
+            return false;
+        }
+
+        let Batcher {
+            vertices: all_vertices,
+            indices_u16,
+            indices_u32,
+            batches,
+            split,
+        } = self;
+
+        let merge = match batches.last() {
+            Some(last) => {
+                !*split && last.key == key && last.vertex_count + count <= MAX_BATCH_VERTICES
+            }
+            None => false,
+        };
+        *split = false;
+        if !merge {
+            batches.push(Batch {
+                key,
+                base_vertex: all_vertices.len() as u32,
+                vertex_count: 0,
+                width: IndexWidth::U16,
+                indices: indices_u16.len() as u32..indices_u16.len() as u32,
+            });
+        }
+        let batch = batches.last_mut().unwrap();
+
+        // The last batch always owns the tail of its index list, so widening
+        // just moves that tail over to the u32 list.
+        if batch.width == IndexWidth::U16 && batch.vertex_count + count > MAX_U16_VERTICES {
+            let start = indices_u32.len() as u32;
+            indices_u32.extend(
+                indices_u16
+                    .drain(batch.indices.start as usize..)
+                    .map(u32::from),
+            );
+            batch.width = IndexWidth::U32;
+            batch.indices = start..indices_u32.len() as u32;
+        }
+
+        let base = batch.vertex_count;
+        match (batch.width, indices) {
+            // The batch stays under `MAX_U16_VERTICES`, so this can't truncate.
+            (IndexWidth::U16, Some(indices)) => {
+                indices_u16.extend(indices.iter().map(|&i| (base + i.into()) as u16));
+            }
+            (IndexWidth::U16, None) => {
+                indices_u16.extend((base..base + count).map(|i| i as u16));
+            }
+            (IndexWidth::U32, Some(indices)) => {
+                indices_u32.extend(indices.iter().map(|&i| base + i.into()));
+            }
+            (IndexWidth::U32, None) => {
+                indices_u32.extend(base..base + count);
+            }
+        }
+        batch.indices.end = match batch.width {
+            IndexWidth::U16 => indices_u16.len() as u32,
+            IndexWidth::U32 => indices_u32.len() as u32,
+        };
+
+        all_vertices.extend_from_slice(vertices);
+        batch.vertex_count += count;
+        true
+    }
+}
+
+impl<K: PartialEq + Copy> Default for Batcher<K> {
+    fn default() -> Self {
+        Batcher::new()
+    }
+}
+
+/// Whether `topology` is made of triangles, so a wireframe changes it.
+pub fn is_triangles(topology: Primitive) -> bool {
+    matches!(topology, Primitive::TriangleList | Primitive::TriangleStrip)
+}
+
+/// Line list indices tracing the edges of every triangle in `indices`, for
+/// wireframes without `PolygonMode::Line`. Edges shared by two triangles
+/// are drawn twice.
+pub fn triangle_edges<I: Copy>(topology: Primitive, indices: &[I]) -> Vec<I> {
+    let triangles: Vec<[I; 3]> = match topology {
+        Primitive::TriangleList => indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect(),
+        Primitive::TriangleStrip => indices.windows(3).map(|t| [t[0], t[1], t[2]]).collect(),
+        _ => return indices.to_vec(),
+    };
+    triangles
+        .iter()
+        .flat_map(|&[a, b, c]| vec![a, b, b, c, c, a])
+        .collect()
+}
@@ -56,15 +56,13 @@ fn main() {
                     winit::event::WindowEvent::KeyboardInput {
                         input:
                             winit::event::KeyboardInput {
-                                virtual_keycode,
+                                virtual_keycode: Some(virtual_keycode),
                                 state: winit::event::ElementState::Pressed,
                                 ..
                             },
                         ..
                     } => {
-                        if let Some(virtual_keycode) = virtual_keycode {
-                            renderer_state.input(virtual_keycode);
-                        }
+                        renderer_state.input(virtual_keycode);
                     }
                     _ => (),
                 }
@@ -73,7 +71,7 @@ fn main() {
                 renderer_state.draw();
             }
             winit::event::Event::RedrawEventsCleared => {
-                renderer_state.backend.window.request_redraw();
+                renderer_state.backend.window.as_ref().unwrap().request_redraw();
             }
             _ => (),
         }
@@ -1,6 +1,6 @@
 use std::{
     cell::RefCell,
-    mem::size_of,
+    mem::size_of_val,
     ptr,
     rc::Rc,
 };
@@ -10,7 +10,7 @@ use hal::{
     buffer,
     memory as m, pool,
     prelude::*,
-    Backend,
+    Backend, IndexType,
 };
 
 use crate::adapter::AdapterState;
@@ -42,17 +42,30 @@ impl<B: Backend> BufferState<B> {
     where
         T: Copy,
     {
+        let upload_size = size_of_val(data_source);
+
+        let mut buffer =
+            BufferState::with_capacity(device_ptr, upload_size as u64, usage, memory_types);
+        // TODO: check transitions: read/write mapping and vertex buffer read
+        buffer.update_data(0, data_source);
+        buffer
+    }
+
+    /// Creates an uninitialized host-visible buffer of at least `size` bytes.
+    pub unsafe fn with_capacity(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        size: u64,
+        usage: buffer::Usage,
+        memory_types: &[MemoryType],
+    ) -> Self {
         let memory: B::Memory;
         let mut buffer: B::Buffer;
-        let size: u64;
-
-        let stride = size_of::<T>();
-        let upload_size = data_source.len() * stride;
+        let mem_size: u64;
 
         {
             let device = &device_ptr.borrow().device;
 
-            buffer = device.create_buffer(upload_size as u64, usage).unwrap();
+            buffer = device.create_buffer(size, usage).unwrap();
             let mem_req = device.get_buffer_requirements(&buffer);
 
             // A note about performance: Using CPU_VISIBLE memory is convenient because it can be
@@ -74,19 +87,14 @@ impl<B: Backend> BufferState<B> {
 
             memory = device.allocate_memory(upload_type, mem_req.size).unwrap();
             device.bind_buffer_memory(&memory, 0, &mut buffer).unwrap();
-            size = mem_req.size;
-
-            // TODO: check transitions: read/write mapping and vertex buffer read
-            let mapping = device.map_memory(&memory, m::Segment::ALL).unwrap();
-            ptr::copy_nonoverlapping(data_source.as_ptr() as *const u8, mapping, upload_size);
-            device.unmap_memory(&memory);
+            mem_size = mem_req.size;
         }
 
         BufferState {
             memory: Some(memory),
             buffer: Some(buffer),
             device: device_ptr,
-            size,
+            size: mem_size,
         }
     }
 
@@ -96,8 +104,7 @@ impl<B: Backend> BufferState<B> {
     {
         let device = &self.device.borrow().device;
 
-        let stride = size_of::<T>();
-        let upload_size = data_source.len() * stride;
+        let upload_size = size_of_val(data_source);
 
         assert!(offset + upload_size as u64 <= self.size);
         let memory = self.memory.as_ref().unwrap();
@@ -111,6 +118,26 @@ impl<B: Backend> BufferState<B> {
         }
     }
 
+    pub fn read_data<T>(&self, offset: u64, data_dest: &mut [T])
+    where
+        T: Copy,
+    {
+        let device = &self.device.borrow().device;
+
+        let download_size = size_of_val(data_dest);
+
+        assert!(offset + download_size as u64 <= self.size);
+        let memory = self.memory.as_ref().unwrap();
+
+        unsafe {
+            let mapping = device
+                .map_memory(memory, m::Segment { offset, size: None })
+                .unwrap();
+            ptr::copy_nonoverlapping(mapping, data_dest.as_mut_ptr() as *mut u8, download_size);
+            device.unmap_memory(memory);
+        }
+    }
+
     pub unsafe fn new_texture(
         device_ptr: Rc<RefCell<DeviceState<B>>>,
         device: &B::Device,
@@ -189,10 +216,133 @@ impl<B: Backend> Drop for BufferState<B> {
     }
 }
 
+/// Element types an index buffer can hold.
+pub trait Index: Copy {
+    const TYPE: IndexType;
+}
+
+impl Index for u16 {
+    const TYPE: IndexType = IndexType::U16;
+}
+
+impl Index for u32 {
+    const TYPE: IndexType = IndexType::U32;
+}
+
+/// A `BufferState` holding indices of a single `IndexType`.
+pub struct IndexBuffer<B: Backend> {
+    buffer: BufferState<B>,
+    index_type: IndexType,
+    count: u32,
+}
+
+impl<B: Backend> IndexBuffer<B> {
+    pub unsafe fn new<I>(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        indices: &[I],
+        memory_types: &[MemoryType],
+    ) -> Self
+    where
+        I: Index,
+    {
+        IndexBuffer {
+            buffer: BufferState::new(device_ptr, indices, buffer::Usage::INDEX, memory_types),
+            index_type: I::TYPE,
+            count: indices.len() as u32,
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
+    pub fn view(&self) -> buffer::IndexBufferView<'_, B> {
+        buffer::IndexBufferView {
+            buffer: self.buffer.get_buffer(),
+            range: buffer::SubRange::WHOLE,
+            index_type: self.index_type,
+        }
+    }
+}
+
+/// Smallest allocation a `StreamBuffer` makes, in bytes.
+const MIN_STREAM_SIZE: u64 = 64 * 1024;
+
+/// A host-visible buffer that is refilled every frame, growing to the next
+/// power of two whenever an upload doesn't fit.
+pub struct StreamBuffer<B: Backend> {
+    buffer: Option<BufferState<B>>,
+    usage: buffer::Usage,
+}
+
+impl<B: Backend> StreamBuffer<B> {
+    pub fn new(usage: buffer::Usage) -> Self {
+        StreamBuffer {
+            buffer: None,
+            usage,
+        }
+    }
+
+    pub fn get_buffer(&self) -> Option<&B::Buffer> {
+        self.buffer.as_ref().map(|b| b.get_buffer())
+    }
+
+    /// Views the stream as an index buffer, if anything was uploaded yet.
+    pub fn index_view(&self, index_type: IndexType) -> Option<buffer::IndexBufferView<'_, B>> {
+        self.get_buffer().map(|buffer| buffer::IndexBufferView {
+            buffer,
+            range: buffer::SubRange::WHOLE,
+            index_type,
+        })
+    }
+
+    /// Replaces the buffer contents with `data_source`.
+    ///
+    /// The caller must make sure the GPU is no longer reading this buffer.
+    pub unsafe fn upload<T>(
+        &mut self,
+        device: &Rc<RefCell<DeviceState<B>>>,
+        memory_types: &[MemoryType],
+        data_source: &[T],
+    ) where
+        T: Copy,
+    {
+        let upload_size = size_of_val(data_source) as u64;
+        if upload_size == 0 {
+            return;
+        }
+
+        if self.buffer.as_ref().is_none_or(|b| b.size < upload_size) {
+            let capacity = upload_size.max(MIN_STREAM_SIZE).next_power_of_two();
+            self.buffer = Some(BufferState::with_capacity(
+                Rc::clone(device),
+                capacity,
+                self.usage,
+                memory_types,
+            ));
+        }
+
+        self.buffer.as_mut().unwrap().update_data(0, data_source);
+    }
+}
+
+/// Resources of every frame that may be in flight at once. A slot is only
+/// reused after waiting on its fence.
+///
+/// There are no acquire semaphores: `PresentationSurface::acquire_image`
+/// blocks on its own fence until the image is available.
 pub struct FramebufferState<B: Backend> {
     command_pools: Option<Vec<B::CommandPool>>,
     command_buffer_lists: Vec<Vec<B::CommandBuffer>>,
     present_semaphores: Option<Vec<B::Semaphore>>,
+    fences: Option<Vec<B::Fence>>,
+    vertex_streams: Vec<StreamBuffer<B>>,
+    index_streams_u16: Vec<StreamBuffer<B>>,
+    index_streams_u32: Vec<StreamBuffer<B>>,
     device: Rc<RefCell<DeviceState<B>>>,
 }
 
@@ -201,6 +351,10 @@ impl<B: Backend> FramebufferState<B> {
         let mut command_pools: Vec<_> = vec![];
         let mut command_buffer_lists = Vec::new();
         let mut present_semaphores: Vec<B::Semaphore> = vec![];
+        let mut fences: Vec<B::Fence> = vec![];
+        let mut vertex_streams = Vec::new();
+        let mut index_streams_u16 = Vec::new();
+        let mut index_streams_u32 = Vec::new();
 
         for _ in 0..num_frames {
             command_pools.push(
@@ -216,30 +370,60 @@ impl<B: Backend> FramebufferState<B> {
             command_buffer_lists.push(Vec::new());
 
             present_semaphores.push(device.borrow().device.create_semaphore().unwrap());
+            // Signalled, so waiting on a slot that was never submitted returns.
+            fences.push(device.borrow().device.create_fence(true).unwrap());
+            vertex_streams.push(StreamBuffer::new(buffer::Usage::VERTEX));
+            index_streams_u16.push(StreamBuffer::new(buffer::Usage::INDEX));
+            index_streams_u32.push(StreamBuffer::new(buffer::Usage::INDEX));
         }
 
         FramebufferState {
             command_pools: Some(command_pools),
             command_buffer_lists,
             present_semaphores: Some(present_semaphores),
+            fences: Some(fences),
+            vertex_streams,
+            index_streams_u16,
+            index_streams_u32,
             device,
         }
     }
 
-    pub fn get_frame_data(
-        &mut self,
-        index: usize,
-    ) -> (
-        &mut B::CommandPool,
-        &mut Vec<B::CommandBuffer>,
-        &mut B::Semaphore,
-    ) {
-        (
-            &mut self.command_pools.as_mut().unwrap()[index],
-            &mut self.command_buffer_lists[index],
-            &mut self.present_semaphores.as_mut().unwrap()[index],
-        )
+    pub fn frames_in_flight(&self) -> usize {
+        self.command_buffer_lists.len()
     }
+
+    /// Blocks until the GPU is done with frame slot `index`.
+    pub unsafe fn wait_for_frame(&self, index: usize) {
+        let device = &self.device.borrow().device;
+        let fence = &self.fences.as_ref().unwrap()[index];
+        device.wait_for_fence(fence, !0).expect("Can't wait for frame fence");
+        device.reset_fence(fence).expect("Can't reset frame fence");
+    }
+
+    pub fn get_frame_data(&mut self, index: usize) -> FrameData<'_, B> {
+        FrameData {
+            command_pool: &mut self.command_pools.as_mut().unwrap()[index],
+            command_buffers: &mut self.command_buffer_lists[index],
+            present_semaphore: &mut self.present_semaphores.as_mut().unwrap()[index],
+            fence: &mut self.fences.as_mut().unwrap()[index],
+            vertex_stream: &mut self.vertex_streams[index],
+            index_stream_u16: &mut self.index_streams_u16[index],
+            index_stream_u32: &mut self.index_streams_u32[index],
+        }
+    }
+}
+
+/// Everything owned by a single frame slot of a `FramebufferState`.
+pub struct FrameData<'a, B: Backend> {
+    pub command_pool: &'a mut B::CommandPool,
+    pub command_buffers: &'a mut Vec<B::CommandBuffer>,
+    pub present_semaphore: &'a mut B::Semaphore,
+    /// Signalled once the frame's submission has completed.
+    pub fence: &'a mut B::Fence,
+    pub vertex_stream: &'a mut StreamBuffer<B>,
+    pub index_stream_u16: &'a mut StreamBuffer<B>,
+    pub index_stream_u32: &'a mut StreamBuffer<B>,
 }
 
 impl<B: Backend> Drop for FramebufferState<B> {
@@ -261,6 +445,10 @@ impl<B: Backend> Drop for FramebufferState<B> {
             for present_semaphore in self.present_semaphores.take().unwrap() {
                 device.destroy_semaphore(present_semaphore);
             }
+
+            for fence in self.fences.take().unwrap() {
+                device.destroy_fence(fence);
+            }
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+use std::{
+    cell::RefCell,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use hal::{adapter::AdapterInfo, prelude::*, Backend};
+
+use crate::device::DeviceState;
+
+const MAGIC: &[u8; 4] = b"CPLC";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 4 + 4 + 8 * 5;
+
+/// FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Identifies the adapter a pipeline cache blob was produced on.
+///
+/// hal 0.6 doesn't expose a driver version. Drivers stamp their own blobs
+/// with one and discard blobs that don't match, so a driver update costs a
+/// cold start but never loads stale data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheKey {
+    pub vendor: u64,
+    pub device: u64,
+    pub name_hash: u64,
+}
+
+impl CacheKey {
+    pub fn new(info: &AdapterInfo) -> Self {
+        CacheKey {
+            vendor: info.vendor as u64,
+            device: info.device as u64,
+            name_hash: fnv1a(info.name.as_bytes()),
+        }
+    }
+
+    /// Prepends a header with this key and a checksum to `data`.
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + data.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        for field in &[
+            self.vendor,
+            self.device,
+            self.name_hash,
+            data.len() as u64,
+            fnv1a(data),
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Returns the data in `bytes` if it was encoded with this key and is
+    /// intact, otherwise why not.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<&'a [u8], &'static str> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err("not a pipeline cache file");
+        }
+
+        let field = |i: usize| {
+            let mut le = [0u8; 8];
+            le.copy_from_slice(&bytes[8 + i * 8..16 + i * 8]);
+            u64::from_le_bytes(le)
+        };
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&bytes[4..8]);
+
+        if u32::from_le_bytes(version) != VERSION {
+            return Err("written by another version");
+        }
+        if (field(0), field(1), field(2)) != (self.vendor, self.device, self.name_hash) {
+            return Err("written for another adapter");
+        }
+
+        let data = &bytes[HEADER_SIZE..];
+        if data.len() as u64 != field(3) || fnv1a(data) != field(4) {
+            return Err("truncated or corrupted");
+        }
+        Ok(data)
+    }
+}
+
+/// A `B::PipelineCache` seeded from, and saved back to, a file.
+///
+/// Without a path it still speeds up pipelines rebuilt during the run.
+pub struct PersistentPipelineCache<B: Backend> {
+    cache: Option<B::PipelineCache>,
+    key: CacheKey,
+    path: Option<PathBuf>,
+    device: Rc<RefCell<DeviceState<B>>>,
+}
+
+impl<B: Backend> PersistentPipelineCache<B> {
+    pub unsafe fn new(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        info: &AdapterInfo,
+        path: Option<PathBuf>,
+    ) -> Self {
+        let key = CacheKey::new(info);
+
+        let bytes = path.as_ref().and_then(|path| fs::read(path).ok());
+        let data = bytes.as_ref().and_then(|bytes| match key.decode(bytes) {
+            Ok(data) => Some(data),
+            Err(reason) => {
+                log::warn!("Ignoring pipeline cache {}: {}", path.as_ref().unwrap().display(), reason);
+                None
+            }
+        });
+
+        let cache = {
+            let device = &device_ptr.borrow().device;
+            // A blob the driver rejects anyway is no reason to fail.
+            device
+                .create_pipeline_cache(data)
+                .or_else(|_| device.create_pipeline_cache(None))
+                .expect("Can't create pipeline cache")
+        };
+
+        PersistentPipelineCache {
+            cache: Some(cache),
+            key,
+            path,
+            device: device_ptr,
+        }
+    }
+
+    pub fn get_cache(&self) -> &B::PipelineCache {
+        self.cache.as_ref().unwrap()
+    }
+
+    pub fn get_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Writes the cache to its file, if it has one. Also done on drop.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let data = unsafe {
+            self.device
+                .borrow()
+                .device
+                .get_pipeline_cache_data(self.get_cache())
+                .map_err(io::Error::other)?
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so a crash never leaves half a file behind.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.key.encode(&data))?;
+        fs::rename(&tmp, path)
+    }
+}
+
+impl<B: Backend> Drop for PersistentPipelineCache<B> {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            log::warn!("Can't save pipeline cache: {}", e);
+        }
+        unsafe {
+            self.device
+                .borrow()
+                .device
+                .destroy_pipeline_cache(self.cache.take().unwrap());
+        }
+    }
+}
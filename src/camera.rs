@@ -0,0 +1,239 @@
+use cgmath::{Matrix4, Rad, SquareMatrix, Vector2, Vector3, Vector4};
+
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+/// Which way positive y points in world space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YAxis {
+    /// Like pixel coordinates, origin in the top left corner.
+    Down,
+    /// Like a math plot, origin in the bottom left corner.
+    Up,
+}
+
+/// A 2D view onto world space, projected onto a viewport measured in pixels.
+///
+/// With the defaults from `new` one world unit is one pixel and the whole
+/// viewport is visible.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera2D {
+    /// World position shown at the center of the viewport.
+    pub position: Vector2<f32>,
+    /// Pixels per world unit.
+    pub zoom: f32,
+    /// Rotation of the world around `position`, counterclockwise on screen.
+    pub rotation: Rad<f32>,
+    pub y_axis: YAxis,
+    viewport: Vector2<f32>,
+}
+
+impl Camera2D {
+    pub fn new(width: u32, height: u32, y_axis: YAxis) -> Self {
+        let viewport = Vector2::new(width as f32, height as f32);
+        Camera2D {
+            position: viewport / 2.0,
+            zoom: 1.0,
+            rotation: Rad(0.0),
+            y_axis,
+            viewport,
+        }
+    }
+
+    pub fn get_viewport(&self) -> Vector2<f32> {
+        self.viewport
+    }
+
+    /// Resizes the viewport, keeping `position` at its center.
+    pub fn set_viewport(&mut self, width: u32, height: u32) {
+        self.viewport = Vector2::new(width as f32, height as f32);
+    }
+
+    /// World space to pixels relative to the viewport center, y down.
+    pub fn view(&self) -> Matrix4<f32> {
+        let flip = match self.y_axis {
+            YAxis::Down => 1.0,
+            YAxis::Up => -1.0,
+        };
+        // On screen y points down, so rotating by -angle there turns the
+        // world counterclockwise, and the flip mirrors that back for y up.
+        let rotation = Matrix4::from_angle_z(-self.rotation * flip);
+
+        Matrix4::from_nonuniform_scale(self.zoom, self.zoom * flip, 1.0)
+            * rotation
+            * Matrix4::from_translation(Vector3::new(-self.position.x, -self.position.y, 0.0))
+    }
+
+    /// Pixels relative to the viewport center to normalized device coordinates.
+    pub fn projection(&self) -> Matrix4<f32> {
+        Matrix4::from_nonuniform_scale(2.0 / self.viewport.x, 2.0 / self.viewport.y, 1.0)
+    }
+
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.projection() * self.view()
+    }
+
+    /// Maps a window position in pixels, origin top left, to world space.
+    pub fn screen_to_world(&self, screen: Vector2<f32>) -> Vector2<f32> {
+        let offset = screen - self.viewport / 2.0;
+        let view = self.view().invert().expect("Camera zoom must not be zero");
+        let world = view * Vector4::new(offset.x, offset.y, 0.0, 1.0);
+        Vector2::new(world.x, world.y)
+    }
+
+    /// Maps a world position to window pixels, origin top left.
+    pub fn world_to_screen(&self, world: Vector2<f32>) -> Vector2<f32> {
+        let offset = self.view() * Vector4::new(world.x, world.y, 0.0, 1.0);
+        Vector2::new(offset.x, offset.y) + self.viewport / 2.0
+    }
+}
+
+/// Drives a `Camera2D` from mouse input: dragging with the left button pans,
+/// the wheel zooms around the cursor.
+///
+/// Input moves a target view, `update` eases the camera towards it.
+#[derive(Debug, Clone, Copy)]
+pub struct ViewController {
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    /// Zoom factor per wheel line.
+    pub zoom_step: f32,
+    /// How quickly the camera catches up with the target, per second. Zero
+    /// jumps straight to it.
+    pub easing: f32,
+    target: Option<(Vector2<f32>, f32)>,
+    /// Screen position and the world position to keep under it while zooming.
+    anchor: Option<(Vector2<f32>, Vector2<f32>)>,
+    cursor: Vector2<f32>,
+    dragging: bool,
+}
+
+impl Default for ViewController {
+    fn default() -> Self {
+        ViewController {
+            min_zoom: 0.05,
+            max_zoom: 50.0,
+            zoom_step: 1.1,
+            easing: 15.0,
+            target: None,
+            anchor: None,
+            cursor: Vector2::new(0.0, 0.0),
+            dragging: false,
+        }
+    }
+}
+
+impl ViewController {
+    pub fn new(min_zoom: f32, max_zoom: f32) -> Self {
+        ViewController {
+            min_zoom,
+            max_zoom,
+            ..ViewController::default()
+        }
+    }
+
+    /// Last cursor position seen, in window pixels.
+    pub fn get_cursor(&self) -> Vector2<f32> {
+        self.cursor
+    }
+
+    /// The camera as it will be once easing has finished.
+    fn target_camera(&self, camera: &Camera2D) -> Camera2D {
+        let (position, zoom) = self.target.unwrap_or((camera.position, camera.zoom));
+        Camera2D {
+            position,
+            zoom,
+            ..*camera
+        }
+    }
+
+    pub fn cursor_moved(&mut self, camera: &Camera2D, screen: Vector2<f32>) {
+        if self.dragging {
+            let target = self.target_camera(camera);
+            let delta = target.screen_to_world(self.cursor) - target.screen_to_world(screen);
+            self.target = Some((target.position + delta, target.zoom));
+            self.anchor = None;
+        }
+        self.cursor = screen;
+    }
+
+    pub fn set_dragging(&mut self, dragging: bool) {
+        self.dragging = dragging;
+    }
+
+    /// Zooms in by `lines` wheel steps around the cursor, out for negative ones.
+    pub fn scroll(&mut self, camera: &Camera2D, lines: f32) {
+        let target = self.target_camera(camera);
+        let world = target.screen_to_world(self.cursor);
+        let zoom = (target.zoom * self.zoom_step.powf(lines)).max(self.min_zoom).min(self.max_zoom);
+
+        self.target = Some((Self::anchored(&target, self.cursor, world, zoom), zoom));
+        self.anchor = Some((self.cursor, world));
+    }
+
+    /// Position that puts `world` at `screen` with `zoom`.
+    fn anchored(camera: &Camera2D, screen: Vector2<f32>, world: Vector2<f32>, zoom: f32) -> Vector2<f32> {
+        let origin = Camera2D {
+            position: Vector2::new(0.0, 0.0),
+            zoom,
+            ..*camera
+        };
+        world - origin.screen_to_world(screen)
+    }
+
+    /// Feeds a window event in. Returns whether it was used.
+    pub fn handle_event(&mut self, camera: &Camera2D, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_moved(camera, Vector2::new(position.x as f32, position.y as f32));
+                self.dragging
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.set_dragging(*state == ElementState::Pressed);
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Touchpads report pixels, treat 50 of them as a line.
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / 50.0,
+                };
+                self.scroll(camera, lines);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves `camera` towards the target view for a frame of `dt` seconds.
+    pub fn update(&mut self, camera: &mut Camera2D, dt: f32) {
+        let (position, zoom) = match self.target {
+            Some(target) => target,
+            None => return,
+        };
+
+        let t = if self.easing > 0.0 {
+            1.0 - (-self.easing * dt).exp()
+        } else {
+            1.0
+        };
+
+        // Zoom eases in log space so zooming in and out feel the same.
+        camera.zoom *= (zoom / camera.zoom).powf(t);
+        camera.position = match self.anchor {
+            Some((screen, world)) => Self::anchored(camera, screen, world, camera.zoom),
+            None => camera.position + (position - camera.position) * t,
+        };
+
+        let d = camera.position - position;
+        if (camera.zoom / zoom - 1.0).abs() < 1e-4 && d.x.abs() < 1e-3 && d.y.abs() < 1e-3 {
+            camera.zoom = zoom;
+            camera.position = position;
+            self.target = None;
+            self.anchor = None;
+        }
+    }
+}
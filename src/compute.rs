@@ -0,0 +1,423 @@
+use std::{cell::RefCell, rc::Rc};
+
+use hal::{
+    buffer, command,
+    format::{self as f, Format},
+    image as i, memory as m,
+    prelude::*,
+    pso, Backend,
+};
+
+use crate::adapter::AdapterState;
+use crate::buffer::BufferState;
+use crate::desc::DescSetLayout;
+use crate::device::DeviceState;
+use crate::offscreen::{read_image, readback_buffer};
+use crate::pipeline::{PipelineError, ENTRY_NAME};
+use crate::reflect::ShaderReflection;
+use crate::shader;
+
+/// Stages that may touch storage resources around a dispatch.
+pub(crate) const SHADER_STAGES: pso::PipelineStage = pso::PipelineStage::from_bits_truncate(
+    pso::PipelineStage::VERTEX_INPUT.bits()
+        | pso::PipelineStage::VERTEX_SHADER.bits()
+        | pso::PipelineStage::FRAGMENT_SHADER.bits()
+        | pso::PipelineStage::COMPUTE_SHADER.bits(),
+);
+
+/// A compute shader with a pipeline layout derived from its SPIR-V.
+pub struct ComputePipelineState<B: Backend> {
+    pub pipeline: Option<B::ComputePipeline>,
+    pub pipeline_layout: Option<B::PipelineLayout>,
+    set_layouts: Vec<Rc<DescSetLayout<B>>>,
+    reflection: ShaderReflection,
+    device: Rc<RefCell<DeviceState<B>>>,
+}
+
+impl<B: Backend> ComputePipelineState<B> {
+    /// Builds a pipeline for shader file `name`, e.g. `"blur.comp"`.
+    pub unsafe fn new(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        name: &str,
+        cache: Option<&B::PipelineCache>,
+    ) -> Result<Self, PipelineError> {
+        Self::new_variant(device_ptr, name, &[], cache)
+    }
+
+    /// Like `new`, for the variant of `name` compiled with `defines`.
+    pub unsafe fn new_variant(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        name: &str,
+        defines: &[(&str, &str)],
+        cache: Option<&B::PipelineCache>,
+    ) -> Result<Self, PipelineError> {
+        let spirv = shader::try_load_variant(name, defines)?;
+        Self::from_spirv(device_ptr, &spirv, pso::Specialization::default(), cache)
+    }
+
+    /// Descriptor set layouts and push constant ranges come from reflecting
+    /// `spirv`.
+    pub unsafe fn from_spirv(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        spirv: &[u32],
+        specialization: pso::Specialization<'static>,
+        cache: Option<&B::PipelineCache>,
+    ) -> Result<Self, PipelineError> {
+        let reflection = ShaderReflection::new(spirv).map_err(PipelineError::Reflect)?;
+        if !reflection.stages.is_empty() && reflection.stages != pso::ShaderStageFlags::COMPUTE {
+            return Err(PipelineError::Interface(format!(
+                "expected a compute shader, got {:?}",
+                reflection.stages
+            )));
+        }
+
+        let set_count = reflection
+            .bindings
+            .iter()
+            .map(|b| b.set + 1)
+            .max()
+            .unwrap_or(0);
+        let set_layouts: Vec<_> = (0..set_count)
+            .map(|set| Rc::new(DescSetLayout::new(Rc::clone(&device_ptr), reflection.set_bindings(set))))
+            .collect();
+
+        let device = &device_ptr.borrow().device;
+
+        let pipeline_layout = device
+            .create_pipeline_layout(
+                set_layouts.iter().map(|layout| layout.get_layout()),
+                &reflection.push_constants,
+            )
+            .map_err(PipelineError::Layout)?;
+
+        let module = match device.create_shader_module(spirv) {
+            Ok(module) => module,
+            Err(error) => {
+                device.destroy_pipeline_layout(pipeline_layout);
+                return Err(PipelineError::ShaderModule {
+                    stage: pso::ShaderStageFlags::COMPUTE,
+                    error,
+                });
+            }
+        };
+
+        let pipeline = device.create_compute_pipeline(
+            &pso::ComputePipelineDesc::new(
+                pso::EntryPoint {
+                    entry: ENTRY_NAME,
+                    module: &module,
+                    specialization,
+                },
+                &pipeline_layout,
+            ),
+            cache,
+        );
+
+        device.destroy_shader_module(module);
+
+        match pipeline {
+            Ok(pipeline) => Ok(ComputePipelineState {
+                pipeline: Some(pipeline),
+                pipeline_layout: Some(pipeline_layout),
+                set_layouts,
+                reflection,
+                device: Rc::clone(&device_ptr),
+            }),
+            Err(e) => {
+                device.destroy_pipeline_layout(pipeline_layout);
+                Err(PipelineError::Creation(e))
+            }
+        }
+    }
+
+    pub fn get_reflection(&self) -> &ShaderReflection {
+        &self.reflection
+    }
+
+    pub fn get_set_layout(&self, set: u32) -> Option<&Rc<DescSetLayout<B>>> {
+        self.set_layouts.get(set as usize)
+    }
+}
+
+impl<B: Backend> Drop for ComputePipelineState<B> {
+    fn drop(&mut self) {
+        let device = &self.device.borrow().device;
+        unsafe {
+            device.destroy_compute_pipeline(self.pipeline.take().unwrap());
+            device.destroy_pipeline_layout(self.pipeline_layout.take().unwrap());
+        }
+    }
+}
+
+/// A host-visible buffer compute shaders read and write. It can also be
+/// bound as a vertex buffer, e.g. for particles.
+pub struct StorageBuffer<B: Backend> {
+    buffer: BufferState<B>,
+    size: u64,
+}
+
+impl<B: Backend> StorageBuffer<B> {
+    pub unsafe fn new(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        adapter: &AdapterState<B>,
+        size: u64,
+    ) -> Self {
+        let usage = buffer::Usage::STORAGE
+            | buffer::Usage::VERTEX
+            | buffer::Usage::TRANSFER_SRC
+            | buffer::Usage::TRANSFER_DST;
+        StorageBuffer {
+            buffer: BufferState::with_capacity(device_ptr, size, usage, &adapter.memory_types),
+            size,
+        }
+    }
+
+    pub fn get_buffer(&self) -> &B::Buffer {
+        self.buffer.get_buffer()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Must not be called while a dispatch using the buffer runs.
+    pub fn write<T: Copy>(&mut self, offset: u64, data: &[T]) {
+        self.buffer.update_data(offset, data);
+    }
+
+    pub fn read<T: Copy>(&self, offset: u64, data: &mut [T]) {
+        self.buffer.read_data(offset, data);
+    }
+}
+
+/// An RGBA8 image compute shaders read and write, kept in
+/// `Layout::General` so it can be sampled as well.
+pub struct StorageImage<B: Backend> {
+    image: Option<B::Image>,
+    memory: Option<B::Memory>,
+    image_view: Option<B::ImageView>,
+    readback: BufferState<B>,
+    extent: i::Extent,
+    row_pitch: u32,
+    device: Rc<RefCell<DeviceState<B>>>,
+}
+
+impl<B: Backend> StorageImage<B> {
+    /// Storage images can't be sRGB, so shaders see the bytes as they are.
+    pub const FORMAT: Format = Format::Rgba8Unorm;
+
+    /// Creates an image of `width` by `height`, filled with `contents` if
+    /// given, which must be of the same size.
+    pub unsafe fn new(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        adapter: &AdapterState<B>,
+        width: u32,
+        height: u32,
+        contents: Option<&image::RgbaImage>,
+    ) -> Self {
+        let extent = i::Extent {
+            width,
+            height,
+            depth: 1,
+        };
+        let (row_pitch, readback) = readback_buffer(&device_ptr, adapter, extent);
+        let range = i::SubresourceRange {
+            aspects: f::Aspects::COLOR,
+            ..Default::default()
+        };
+
+        let (image, memory, image_view) = {
+            let device = &device_ptr.borrow().device;
+
+            let mut image = device
+                .create_image(
+                    i::Kind::D2(width as i::Size, height as i::Size, 1, 1),
+                    1,
+                    Self::FORMAT,
+                    i::Tiling::Optimal,
+                    i::Usage::STORAGE
+                        | i::Usage::SAMPLED
+                        | i::Usage::TRANSFER_SRC
+                        | i::Usage::TRANSFER_DST,
+                    i::ViewCapabilities::empty(),
+                )
+                .unwrap();
+            let req = device.get_image_requirements(&image);
+
+            let device_type = adapter
+                .memory_types
+                .iter()
+                .enumerate()
+                .position(|(id, memory_type)| {
+                    req.type_mask & (1 << id) != 0
+                        && memory_type.properties.contains(m::Properties::DEVICE_LOCAL)
+                })
+                .unwrap()
+                .into();
+
+            let memory = device.allocate_memory(device_type, req.size).unwrap();
+            device.bind_image_memory(&memory, 0, &mut image).unwrap();
+
+            let image_view = device
+                .create_image_view(
+                    &image,
+                    i::ViewKind::D2,
+                    Self::FORMAT,
+                    f::Swizzle::NO,
+                    range.clone(),
+                )
+                .unwrap();
+
+            (image, memory, image_view)
+        };
+
+        // The staging buffer must outlive the submission below.
+        let staging = contents.map(|contents| {
+            assert_eq!(
+                (width, height),
+                contents.dimensions(),
+                "Contents must match the image size"
+            );
+            let device = &device_ptr.borrow().device;
+            BufferState::new_texture(
+                Rc::clone(&device_ptr),
+                device,
+                contents,
+                adapter,
+                buffer::Usage::TRANSFER_SRC,
+            )
+        });
+
+        device_ptr.borrow_mut().submit_once(|cmd_buffer| {
+            let state = match &staging {
+                None => (i::Access::empty(), i::Layout::Undefined),
+                Some((buffer, _, row_pitch, stride)) => {
+                    cmd_buffer.pipeline_barrier(
+                        pso::PipelineStage::TOP_OF_PIPE..pso::PipelineStage::TRANSFER,
+                        m::Dependencies::empty(),
+                        &[m::Barrier::Image {
+                            states: (i::Access::empty(), i::Layout::Undefined)
+                                ..(i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal),
+                            target: &image,
+                            families: None,
+                            range: range.clone(),
+                        }],
+                    );
+                    cmd_buffer.copy_buffer_to_image(
+                        buffer.get_buffer(),
+                        &image,
+                        i::Layout::TransferDstOptimal,
+                        &[command::BufferImageCopy {
+                            buffer_offset: 0,
+                            buffer_width: row_pitch / (*stride as u32),
+                            buffer_height: height,
+                            image_layers: i::SubresourceLayers {
+                                aspects: f::Aspects::COLOR,
+                                level: 0,
+                                layers: 0..1,
+                            },
+                            image_offset: i::Offset { x: 0, y: 0, z: 0 },
+                            image_extent: extent,
+                        }],
+                    );
+                    (i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal)
+                }
+            };
+
+            cmd_buffer.pipeline_barrier(
+                pso::PipelineStage::TRANSFER..SHADER_STAGES,
+                m::Dependencies::empty(),
+                &[m::Barrier::Image {
+                    states: state
+                        ..(
+                            i::Access::SHADER_READ | i::Access::SHADER_WRITE,
+                            i::Layout::General,
+                        ),
+                    target: &image,
+                    families: None,
+                    range: range.clone(),
+                }],
+            );
+        });
+
+        StorageImage {
+            image: Some(image),
+            memory: Some(memory),
+            image_view: Some(image_view),
+            readback,
+            extent,
+            row_pitch,
+            device: device_ptr,
+        }
+    }
+
+    pub fn get_view(&self) -> &B::ImageView {
+        self.image_view.as_ref().unwrap()
+    }
+
+    pub fn extent(&self) -> i::Extent {
+        self.extent
+    }
+
+    /// Copies the image back to the host. No work using it may be in flight.
+    pub unsafe fn read_pixels(&mut self) -> image::RgbaImage {
+        read_image(
+            &self.device,
+            self.image.as_ref().unwrap(),
+            i::Layout::General,
+            (pso::PipelineStage::COMPUTE_SHADER, i::Access::SHADER_WRITE),
+            self.extent,
+            self.row_pitch,
+            &mut self.readback,
+        )
+    }
+}
+
+impl<B: Backend> Drop for StorageImage<B> {
+    fn drop(&mut self) {
+        let device = &self.device.borrow().device;
+        unsafe {
+            device.destroy_image_view(self.image_view.take().unwrap());
+            device.destroy_image(self.image.take().unwrap());
+            device.free_memory(self.memory.take().unwrap());
+        }
+    }
+}
+
+/// A resource bound to a compute descriptor set.
+pub enum ComputeResource<'a, B: Backend> {
+    Buffer(&'a StorageBuffer<B>),
+    Image(&'a StorageImage<B>),
+}
+
+impl<'a, B: Backend> ComputeResource<'a, B> {
+    pub fn descriptor(&self) -> pso::Descriptor<'a, B> {
+        match *self {
+            ComputeResource::Buffer(buffer) => {
+                pso::Descriptor::Buffer(buffer.get_buffer(), buffer::SubRange::WHOLE)
+            }
+            ComputeResource::Image(image) => {
+                pso::Descriptor::Image(image.get_view(), i::Layout::General)
+            }
+        }
+    }
+
+    /// Whether it can be bound where a shader expects `ty`.
+    pub fn fits(&self, ty: &pso::DescriptorType) -> bool {
+        matches!(
+            (self, ty),
+            (
+                ComputeResource::Buffer(_),
+                pso::DescriptorType::Buffer {
+                    ty: pso::BufferDescriptorType::Storage { .. },
+                    format: pso::BufferDescriptorFormat::Structured { .. },
+                },
+            ) | (
+                ComputeResource::Image(_),
+                pso::DescriptorType::Image {
+                    ty: pso::ImageDescriptorType::Storage { .. },
+                },
+            )
+        )
+    }
+}
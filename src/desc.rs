@@ -1,5 +1,6 @@
 use std::{
     cell::RefCell,
+    iter,
     rc::Rc,
 };
 
@@ -13,6 +14,7 @@ use crate::device::DeviceState;
 
 pub struct DescSetLayout<B: Backend> {
     layout: Option<B::DescriptorSetLayout>,
+    bindings: Vec<pso::DescriptorSetLayoutBinding>,
     pub device: Rc<RefCell<DeviceState<B>>>,
 }
 
@@ -24,23 +26,29 @@ impl<B: Backend> DescSetLayout<B> {
         let desc_set_layout = device
             .borrow()
             .device
-            .create_descriptor_set_layout(bindings, &[])
+            .create_descriptor_set_layout(&bindings, &[])
             .ok();
 
         DescSetLayout {
             layout: desc_set_layout,
+            bindings,
             device,
         }
     }
 
-    pub unsafe fn create_desc_set(self, desc_pool: &mut B::DescriptorPool) -> DescSet<B> {
-        let desc_set = desc_pool
-            .allocate_set(self.layout.as_ref().unwrap())
-            .unwrap();
-        DescSet {
-            layout: self,
-            set: Some(desc_set),
-        }
+    pub fn get_layout(&self) -> &B::DescriptorSetLayout {
+        self.layout.as_ref().unwrap()
+    }
+
+    pub fn get_bindings(&self) -> &[pso::DescriptorSetLayoutBinding] {
+        &self.bindings
+    }
+
+    pub unsafe fn create_desc_set(
+        self,
+        allocator: &mut DescAllocator<B>,
+    ) -> Result<DescSet<B>, pso::AllocationError> {
+        DescSet::new(Rc::new(self), allocator)
     }
 }
 
@@ -53,9 +61,16 @@ impl<B: Backend> Drop for DescSetLayout<B> {
     }
 }
 
+/// A set allocated from a `DescAllocator`.
+///
+/// Dropping it doesn't free the set; it stays counted in its pool until
+/// given back with `DescAllocator::free` or the allocator is dropped.
 pub struct DescSet<B: Backend> {
     pub set: Option<B::DescriptorSet>,
-    pub layout: DescSetLayout<B>,
+    /// Shared when several sets are allocated with the same layout.
+    pub layout: Rc<DescSetLayout<B>>,
+    /// Index of the `DescAllocator` pool the set came from.
+    pool: usize,
 }
 
 pub struct DescSetWrite<W> {
@@ -65,6 +80,19 @@ pub struct DescSetWrite<W> {
 }
 
 impl<B: Backend> DescSet<B> {
+    /// Allocates a set with `layout` from `allocator`.
+    pub unsafe fn new(
+        layout: Rc<DescSetLayout<B>>,
+        allocator: &mut DescAllocator<B>,
+    ) -> Result<Self, pso::AllocationError> {
+        let (pool, desc_set) = allocator.allocate(layout.get_layout())?;
+        Ok(DescSet {
+            layout,
+            set: Some(desc_set),
+            pool,
+        })
+    }
+
     pub unsafe fn write_to_state<'a, 'b: 'a, W>(
         &'b mut self,
         write: Vec<DescSetWrite<W>>,
@@ -87,6 +115,124 @@ impl<B: Backend> DescSet<B> {
     }
 
     pub fn get_layout(&self) -> &B::DescriptorSetLayout {
-        self.layout.layout.as_ref().unwrap()
+        self.layout.get_layout()
+    }
+}
+/// Hands out descriptor sets from a list of pools, adding a pool whenever
+/// the existing ones are full.
+///
+/// Every pool has room for `sets_per_pool` sets and the descriptors in
+/// `ranges`. Freed sets go back to their pool, which reuses the room.
+pub struct DescAllocator<B: Backend> {
+    pools: Vec<B::DescriptorPool>,
+    /// Sets allocated from each pool and not freed yet.
+    live: Vec<usize>,
+    sets_per_pool: usize,
+    ranges: Vec<pso::DescriptorRangeDesc>,
+    device: Rc<RefCell<DeviceState<B>>>,
+}
+
+impl<B: Backend> DescAllocator<B> {
+    pub fn new(
+        device: Rc<RefCell<DeviceState<B>>>,
+        sets_per_pool: usize,
+        ranges: Vec<pso::DescriptorRangeDesc>,
+    ) -> Self {
+        DescAllocator {
+            pools: Vec::new(),
+            live: Vec::new(),
+            sets_per_pool,
+            ranges,
+            device,
+        }
+    }
+
+    /// Allocates a set with `layout`, returning the index of its pool along
+    /// with it.
+    pub unsafe fn allocate(
+        &mut self,
+        layout: &B::DescriptorSetLayout,
+    ) -> Result<(usize, B::DescriptorSet), pso::AllocationError> {
+        for pool in 0..self.pools.len() {
+            if self.live[pool] == self.sets_per_pool {
+                continue;
+            }
+            match self.pools[pool].allocate_set(layout) {
+                Ok(set) => {
+                    self.live[pool] += 1;
+                    return Ok((pool, set));
+                }
+                // Sets may need more of some descriptor than is left.
+                Err(pso::AllocationError::OutOfPoolMemory) | Err(pso::AllocationError::FragmentedPool) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let device = &self.device.borrow().device;
+        let mut pool = device
+            .create_descriptor_pool(
+                self.sets_per_pool,
+                self.ranges.iter(),
+                pso::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            )
+            .map_err(pso::AllocationError::OutOfMemory)?;
+        // Only keep the pool once a set came out of it.
+        let set = match pool.allocate_set(layout) {
+            Ok(set) => set,
+            Err(e) => {
+                device.destroy_descriptor_pool(pool);
+                return Err(e);
+            }
+        };
+        self.pools.push(pool);
+        self.live.push(1);
+        Ok((self.pools.len() - 1, set))
+    }
+
+    /// Returns `desc` to its pool, leaving it without a set. The GPU must be
+    /// done with it.
+    pub unsafe fn free(&mut self, desc: &mut DescSet<B>) {
+        if let Some(set) = desc.set.take() {
+            self.pools[desc.pool].free(iter::once(set));
+            self.live[desc.pool] -= 1;
+        }
+    }
+
+    /// Whether the pools hold descriptors of type `ty` at all.
+    pub fn supports(&self, ty: &pso::DescriptorType) -> bool {
+        // Pools don't tell read-only storage descriptors apart.
+        fn writable(ty: pso::DescriptorType) -> pso::DescriptorType {
+            match ty {
+                pso::DescriptorType::Buffer {
+                    ty: pso::BufferDescriptorType::Storage { .. },
+                    format,
+                } => pso::DescriptorType::Buffer {
+                    ty: pso::BufferDescriptorType::Storage { read_only: false },
+                    format,
+                },
+                pso::DescriptorType::Image {
+                    ty: pso::ImageDescriptorType::Storage { .. },
+                } => pso::DescriptorType::Image {
+                    ty: pso::ImageDescriptorType::Storage { read_only: false },
+                },
+                ty => ty,
+            }
+        }
+        self.ranges.iter().any(|range| writable(range.ty) == writable(*ty))
     }
-}
\ No newline at end of file
+
+    pub fn pool_count(&self) -> usize {
+        self.pools.len()
+    }
+}
+
+impl<B: Backend> Drop for DescAllocator<B> {
+    fn drop(&mut self) {
+        let device = &self.device.borrow().device;
+        unsafe {
+            for pool in self.pools.drain(..) {
+                device.destroy_descriptor_pool(pool);
+            }
+        }
+    }
+}
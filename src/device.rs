@@ -1,29 +1,44 @@
+use std::iter;
+
 use hal::{
     adapter::Adapter,
+    command, pool,
     prelude::*,
     queue::QueueGroup,
     Backend,
 };
 
+/// Features enabled when the adapter has them, see `DeviceState::features`.
+const OPTIONAL_FEATURES: hal::Features = hal::Features::NON_FILL_POLYGON_MODE;
+
 pub struct DeviceState<B: Backend> {
     pub device: B::Device,
     pub physical_device: B::PhysicalDevice,
     pub queues: QueueGroup<B>,
+    /// The features the device was opened with.
+    pub features: hal::Features,
 }
 
 impl<B: Backend> DeviceState<B> {
-    pub fn new(adapter: Adapter<B>, surface: &B::Surface) -> Self {
+    /// Without a `surface` any graphics capable queue family is used. Families
+    /// that can run compute work as well are preferred.
+    pub fn new(adapter: Adapter<B>, surface: Option<&B::Surface>) -> Self {
+        let usable = |family: &&B::QueueFamily| {
+            surface.is_none_or(|surface| surface.supports_queue_family(family))
+                && family.queue_type().supports_graphics()
+        };
         let family = adapter
             .queue_families
             .iter()
-            .find(|family| {
-                surface.supports_queue_family(family) && family.queue_type().supports_graphics()
-            })
+            .filter(usable)
+            .find(|family| family.queue_type().supports_compute())
+            .or_else(|| adapter.queue_families.iter().find(usable))
             .unwrap();
+        let features = adapter.physical_device.features() & OPTIONAL_FEATURES;
         let mut gpu = unsafe {
             adapter
                 .physical_device
-                .open(&[(family, &[1.0])], hal::Features::empty())
+                .open(&[(family, &[1.0])], features)
                 .unwrap()
         };
 
@@ -31,6 +46,32 @@ impl<B: Backend> DeviceState<B> {
             device: gpu.device,
             queues: gpu.queue_groups.pop().unwrap(),
             physical_device: adapter.physical_device,
+            features,
         }
     }
+
+    /// Records commands with `record` into a one-off command buffer, submits
+    /// it and blocks until it has executed.
+    pub unsafe fn submit_once<F>(&mut self, record: F)
+    where
+        F: FnOnce(&mut B::CommandBuffer),
+    {
+        let mut pool = self
+            .device
+            .create_command_pool(self.queues.family, pool::CommandPoolCreateFlags::TRANSIENT)
+            .expect("Can't create command pool");
+        let fence = self.device.create_fence(false).expect("Can't create fence");
+
+        let mut cmd_buffer = pool.allocate_one(command::Level::Primary);
+        cmd_buffer.begin_primary(command::CommandBufferFlags::ONE_TIME_SUBMIT);
+        record(&mut cmd_buffer);
+        cmd_buffer.finish();
+
+        self.queues.queues[0].submit_without_semaphores(iter::once(&cmd_buffer), Some(&fence));
+        self.device.wait_for_fence(&fence, !0).unwrap();
+
+        self.device.destroy_fence(fence);
+        pool.free(iter::once(cmd_buffer));
+        self.device.destroy_command_pool(pool);
+    }
 }
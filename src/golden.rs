@@ -0,0 +1,216 @@
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use image::{Rgba, RgbaImage};
+
+use crate::backend::{create_headless_backend, DefaultBackend};
+use crate::render::RendererState;
+
+/// Set to anything to (re)write reference images instead of comparing.
+pub const BLESS_VAR: &str = "GOLDEN_BLESS";
+
+/// How far a rendering may stray from its reference and still pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// Largest accepted difference of any single channel.
+    pub per_channel: u8,
+    /// How many pixels may exceed `per_channel` before the check fails.
+    pub max_differing_pixels: usize,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            per_channel: 2,
+            max_differing_pixels: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    MissingReference {
+        reference: PathBuf,
+        actual: PathBuf,
+    },
+    SizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    Mismatch {
+        differing_pixels: usize,
+        allowed: usize,
+        diff: PathBuf,
+    },
+    Image(image::ImageError),
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::MissingReference { reference, actual } => write!(
+                f,
+                "no reference at {}, rendering written to {} (rerun with {}=1 to accept it)",
+                reference.display(),
+                actual.display(),
+                BLESS_VAR
+            ),
+            GoldenError::SizeMismatch { expected, actual } => write!(
+                f,
+                "rendered {}x{} but the reference is {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            GoldenError::Mismatch {
+                differing_pixels,
+                allowed,
+                diff,
+            } => write!(
+                f,
+                "{} pixels differ ({} allowed), see {}",
+                differing_pixels,
+                allowed,
+                diff.display()
+            ),
+            GoldenError::Image(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+impl From<image::ImageError> for GoldenError {
+    fn from(e: image::ImageError) -> Self {
+        GoldenError::Image(e)
+    }
+}
+
+/// Result of comparing two images of the same size.
+pub struct Comparison {
+    pub differing_pixels: usize,
+    /// Matching pixels dimmed to grey, differing ones in solid red.
+    pub diff: RgbaImage,
+}
+
+/// Compares `actual` against `expected` pixel by pixel.
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage, per_channel: u8) -> Comparison {
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut differing_pixels = 0;
+
+    for ((a, e), d) in actual.pixels().zip(expected.pixels()).zip(diff.pixels_mut()) {
+        let differs = a
+            .0
+            .iter()
+            .zip(e.0.iter())
+            .any(|(a, e)| (*a as i16 - *e as i16).unsigned_abs() > per_channel as u16);
+
+        *d = if differs {
+            differing_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let luma = (e[0] as u32 + e[1] as u32 + e[2] as u32) / 3 / 4 + 32;
+            Rgba([luma as u8, luma as u8, luma as u8, 255])
+        };
+    }
+
+    Comparison {
+        differing_pixels,
+        diff,
+    }
+}
+
+/// Checks renderings against reference PNGs stored in one directory.
+pub struct Golden {
+    pub reference_dir: PathBuf,
+    /// Where renderings and diffs of failed checks are written.
+    pub output_dir: PathBuf,
+    pub tolerance: Tolerance,
+}
+
+impl Golden {
+    /// Uses `reference_dir` for references and its `failures` subdirectory
+    /// for output.
+    pub fn new<P: AsRef<Path>>(reference_dir: P) -> Self {
+        let reference_dir = reference_dir.as_ref().to_path_buf();
+        Golden {
+            output_dir: reference_dir.join("failures"),
+            reference_dir,
+            tolerance: Tolerance::default(),
+        }
+    }
+
+    pub fn tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Compares `actual` with `<reference_dir>/<name>.png`.
+    ///
+    /// With `GOLDEN_BLESS` set the reference is overwritten instead.
+    pub fn check(&self, name: &str, actual: &RgbaImage) -> Result<(), GoldenError> {
+        let reference = self.reference_dir.join(format!("{}.png", name));
+
+        if env::var_os(BLESS_VAR).is_some() {
+            fs::create_dir_all(&self.reference_dir).map_err(image::ImageError::IoError)?;
+            actual.save(&reference)?;
+            return Ok(());
+        }
+
+        if !reference.exists() {
+            let actual_path = self.write_output(name, "actual", actual)?;
+            return Err(GoldenError::MissingReference {
+                reference,
+                actual: actual_path,
+            });
+        }
+
+        let expected = image::open(&reference)?.to_rgba8();
+        if expected.dimensions() != actual.dimensions() {
+            return Err(GoldenError::SizeMismatch {
+                expected: expected.dimensions(),
+                actual: actual.dimensions(),
+            });
+        }
+
+        let comparison = compare(actual, &expected, self.tolerance.per_channel);
+        if comparison.differing_pixels > self.tolerance.max_differing_pixels {
+            self.write_output(name, "actual", actual)?;
+            let diff = self.write_output(name, "diff", &comparison.diff)?;
+            return Err(GoldenError::Mismatch {
+                differing_pixels: comparison.differing_pixels,
+                allowed: self.tolerance.max_differing_pixels,
+                diff,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn write_output(&self, name: &str, kind: &str, img: &RgbaImage) -> Result<PathBuf, GoldenError> {
+        fs::create_dir_all(&self.output_dir).map_err(image::ImageError::IoError)?;
+        let path = self.output_dir.join(format!("{}.{}.png", name, kind));
+        img.save(&path)?;
+        Ok(path)
+    }
+}
+
+/// Renders one frame offscreen and reads it back.
+///
+/// `scene` runs between `begin_frame` and `end_frame`. Returns `None` when
+/// no adapter is available.
+pub fn render_headless<F>(width: u32, height: u32, scene: F) -> Option<RgbaImage>
+where
+    F: FnOnce(&mut RendererState<DefaultBackend>),
+{
+    let backend = create_headless_backend()?;
+    let mut renderer = unsafe {
+        RendererState::new_headless(backend, hal::window::Extent2D { width, height })
+    };
+
+    renderer.begin_frame();
+    scene(&mut renderer);
+    renderer.end_frame();
+
+    Some(renderer.read_pixels())
+}
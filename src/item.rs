@@ -1,7 +1,9 @@
 use std::{
     cell::RefCell,
     iter,
+    mem::size_of,
     rc::Rc,
+    slice,
 };
 
 use hal::{
@@ -15,7 +17,7 @@ use hal::{
 };
 
 use crate::buffer::BufferState;
-use crate::desc::{DescSet, DescSetWrite};
+use crate::desc::{DescSet, DescSetLayout, DescSetWrite};
 use crate::device::DeviceState;
 use crate::adapter::AdapterState;
 
@@ -39,7 +41,7 @@ impl<B: Backend> Uniform<B> {
     {
         let buffer = BufferState::new(
             Rc::clone(&device),
-            &data,
+            data,
             buffer::Usage::UNIFORM,
             memory_types,
         );
@@ -66,6 +68,197 @@ impl<B: Backend> Uniform<B> {
     pub fn get_layout(&self) -> &B::DescriptorSetLayout {
         self.desc.as_ref().unwrap().get_layout()
     }
+
+    pub fn get_desc_layout(&self) -> &DescSetLayout<B> {
+        &self.desc.as_ref().unwrap().layout
+    }
+}
+
+/// Blocks a `DynamicUniform` region has room for at least.
+const MIN_DYNAMIC_BLOCKS: u64 = 64;
+
+/// Where the blocks of a `DynamicUniform` go, kept apart from the buffer
+/// so the layout doesn't need a device.
+///
+/// The buffer is a ring of one region per frame in flight, each holding
+/// `region_blocks` blocks `stride` bytes apart.
+#[derive(Debug, Clone)]
+pub struct UniformBlocks {
+    block_size: u64,
+    /// `block_size` rounded up to `min_uniform_buffer_offset_alignment`.
+    stride: u64,
+    regions: u64,
+    region_blocks: u64,
+    staged: Vec<u8>,
+}
+
+impl UniformBlocks {
+    pub fn new(block_size: u64, alignment: u64, regions: usize) -> Self {
+        let alignment = alignment.max(1);
+        UniformBlocks {
+            block_size,
+            stride: block_size.div_ceil(alignment) * alignment,
+            regions: regions as u64,
+            region_blocks: 0,
+            staged: Vec::new(),
+        }
+    }
+
+    /// Drops the staged blocks, to start on the next frame.
+    pub fn clear(&mut self) {
+        self.staged.clear();
+    }
+
+    /// Stages `block` and returns its index for `offset`.
+    pub fn push<T: Copy>(&mut self, block: &T) -> u32 {
+        assert!(size_of::<T>() as u64 <= self.block_size, "Uniform block too large");
+        let index = self.len();
+        let start = self.staged.len();
+        self.staged.resize(start + self.stride as usize, 0);
+        let bytes = unsafe { slice::from_raw_parts(block as *const T as *const u8, size_of::<T>()) };
+        self.staged[start..start + bytes.len()].copy_from_slice(bytes);
+        index
+    }
+
+    /// Number of blocks staged.
+    pub fn len(&self) -> u32 {
+        (self.staged.len() as u64 / self.stride) as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    /// Grows the regions to fit the staged blocks. Returns whether they
+    /// grew, so the buffer must be created again with `get_buffer_size`.
+    pub fn reserve(&mut self) -> bool {
+        let blocks = u64::from(self.len());
+        if self.region_blocks >= blocks && self.region_blocks > 0 {
+            return false;
+        }
+        self.region_blocks = blocks.max(MIN_DYNAMIC_BLOCKS).next_power_of_two();
+        true
+    }
+
+    pub fn get_buffer_size(&self) -> u64 {
+        self.region_blocks * self.stride * self.regions
+    }
+
+    pub fn get_block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    pub fn get_staged(&self) -> &[u8] {
+        &self.staged
+    }
+
+    /// The dynamic offset of block `index` in `region`.
+    pub fn offset(&self, region: usize, index: u32) -> u32 {
+        ((region as u64 * self.region_blocks + u64::from(index)) * self.stride) as u32
+    }
+}
+
+/// Uniform blocks written per draw into one buffer and picked with a
+/// dynamic offset, so draws with distinct uniforms share a descriptor set.
+///
+/// Blocks are staged with `push` during a frame and copied into that frame's
+/// region of the buffer by `upload`, laid out by `UniformBlocks`.
+pub struct DynamicUniform<B: Backend> {
+    buffer: Option<BufferState<B>>,
+    pub desc: Option<DescSet<B>>,
+    binding: u32,
+    blocks: UniformBlocks,
+    device: Rc<RefCell<DeviceState<B>>>,
+}
+
+impl<B: Backend> DynamicUniform<B> {
+    /// `desc` must have a dynamic uniform buffer at `binding`.
+    pub fn new(
+        device: Rc<RefCell<DeviceState<B>>>,
+        limits: &hal::Limits,
+        block_size: u64,
+        regions: usize,
+        desc: DescSet<B>,
+        binding: u32,
+    ) -> Self {
+        DynamicUniform {
+            buffer: None,
+            desc: Some(desc),
+            binding,
+            blocks: UniformBlocks::new(block_size, limits.min_uniform_buffer_offset_alignment, regions),
+            device,
+        }
+    }
+
+    /// Drops the staged blocks, to start on the next frame.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Stages `block` and returns its index for `offset`.
+    pub fn push<T: Copy>(&mut self, block: &T) -> u32 {
+        self.blocks.push(block)
+    }
+
+    /// Number of blocks staged.
+    pub fn len(&self) -> u32 {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Copies the staged blocks into `region`.
+    ///
+    /// The caller must make sure the GPU is done with that region. Growing
+    /// the buffer waits for the device to be idle.
+    pub unsafe fn upload(&mut self, region: usize, memory_types: &[MemoryType]) {
+        if self.blocks.is_empty() {
+            return;
+        }
+
+        if self.blocks.reserve() {
+            // Other regions may still be read by frames in flight, and the
+            // descriptor set is rewritten.
+            if self.buffer.is_some() {
+                self.device.borrow().device.wait_idle().unwrap();
+            }
+            let buffer = BufferState::with_capacity(
+                Rc::clone(&self.device),
+                self.blocks.get_buffer_size(),
+                buffer::Usage::UNIFORM,
+                memory_types,
+            );
+            self.desc.as_mut().unwrap().write_to_state(
+                vec![DescSetWrite {
+                    binding: self.binding,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Buffer(
+                        buffer.get_buffer(),
+                        buffer::SubRange {
+                            offset: 0,
+                            size: Some(self.blocks.get_block_size()),
+                        },
+                    )),
+                }],
+                &mut self.device.borrow_mut().device,
+            );
+            self.buffer = Some(buffer);
+        }
+
+        let start = u64::from(self.blocks.offset(region, 0));
+        self.buffer.as_mut().unwrap().update_data(start, self.blocks.get_staged());
+    }
+
+    /// The dynamic offset of block `index` as uploaded to `region`.
+    pub fn offset(&self, region: usize, index: u32) -> u32 {
+        self.blocks.offset(region, index)
+    }
+
+    pub fn get_desc_layout(&self) -> &DescSetLayout<B> {
+        &self.desc.as_ref().unwrap().layout
+    }
 }
 
 pub struct ImageState<B: Backend> {
@@ -89,7 +282,7 @@ impl<B: Backend> ImageState<B> {
     ) -> Self {
         let (buffer, dims, row_pitch, stride) = BufferState::new_texture(
             Rc::clone(&desc.layout.device),
-            &mut device_state.device,
+            &device_state.device,
             img,
             adapter,
             usage,
@@ -161,7 +354,7 @@ impl<B: Backend> ImageState<B> {
             device,
         );
 
-        let mut transfered_image_fence = device.create_fence(false).expect("Can't create fence");
+        let transfered_image_fence = device.create_fence(false).expect("Can't create fence");
 
         // copy buffer to texture
         {
@@ -192,7 +385,7 @@ impl<B: Backend> ImageState<B> {
                 &[command::BufferImageCopy {
                     buffer_offset: 0,
                     buffer_width: row_pitch / (stride as u32),
-                    buffer_height: dims.height as u32,
+                    buffer_height: dims.height,
                     image_layers: i::SubresourceLayers {
                         aspects: f::Aspects::COLOR,
                         level: 0,
@@ -227,13 +420,13 @@ impl<B: Backend> ImageState<B> {
 
             device_state.queues.queues[0].submit_without_semaphores(
                 iter::once(&cmd_buffer),
-                Some(&mut transfered_image_fence),
+                Some(&transfered_image_fence),
             );
         }
 
         ImageState {
-            desc: desc,
-            buffer: buffer,
+            desc,
+            buffer,
             sampler: Some(sampler),
             image_view: Some(image_view),
             image: Some(image),
@@ -254,6 +447,10 @@ impl<B: Backend> ImageState<B> {
     pub fn get_layout(&self) -> &B::DescriptorSetLayout {
         self.desc.get_layout()
     }
+
+    pub fn get_desc_layout(&self) -> &DescSetLayout<B> {
+        &self.desc.layout
+    }
 }
 
 impl<B: Backend> Drop for ImageState<B> {
@@ -1,22 +1,35 @@
 #![allow(dead_code, unused_imports)]
+#![allow(clippy::missing_safety_doc)]
 
 // #[macro_use]
 // extern crate log;
 
 #[macro_use]
-mod shapes;
+pub mod shapes;
 
 pub mod adapter;
 pub mod backend;
+pub mod batch;
 pub mod buffer;
+pub mod cache;
+pub mod camera;
+pub mod compute;
 pub mod desc;
 pub mod device;
+pub mod golden;
 pub mod item;
+pub mod offscreen;
 pub mod pipeline;
+pub mod preprocess;
+pub mod reflect;
 pub mod render;
+pub mod shader;
 pub mod swapchain;
+pub mod variants;
+pub mod vertex;
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::excessive_precision)]
 mod shape_tests {
     use crate::shapes::*;
     use cgmath::Vector2;
@@ -90,4 +103,675 @@ mod shape_tests {
             assert_eq!(false, t.contains(Vector2::new(100f32, 25f32)))
         }
     }
+
+    #[test]
+    fn rec_queries() {
+        let r = rec!(10f32, 20f32, 40f32, 20f32);
+        assert_eq!(120f32, r.perimeter());
+        assert_eq!(Vector2::new(30f32, 30f32), r.centroid());
+        assert_eq!(r.centroid(), r.bounding_center());
+
+        let m = r.second_moment();
+        assert_eq!(40f32 * 8000f32 / 12f32, m.ix);
+        assert_eq!(20f32 * 64000f32 / 12f32, m.iy);
+        assert_eq!(0f32, m.ixy);
+        assert_eq!(m.ix + m.iy, m.polar());
+    }
+
+    #[test]
+    fn tri_queries() {
+        let t = tri!(0f64, 0f64, 30f64, 0f64, 0f64, 60f64);
+        assert_eq!(30f64 + 60f64 + 4500f64.sqrt(), t.perimeter());
+        assert_eq!(Vector2::new(10f64, 20f64), t.centroid());
+        assert_eq!(Vector2::new(15f64, 30f64), t.bounding_center());
+
+        // Right triangle with legs b = 30 and h = 60 along the axes.
+        let m = t.second_moment();
+        assert!((m.ix - 30f64 * 60f64.powi(3) / 36f64).abs() < 1e-6);
+        assert!((m.iy - 60f64 * 30f64.powi(3) / 36f64).abs() < 1e-6);
+        assert!((m.ixy + 30f64.powi(2) * 60f64.powi(2) / 72f64).abs() < 1e-6);
+    }
+
+    #[test]
+    fn f64_geometry() {
+        let r = rec!(0f64, 0f64, 1e9f64 + 0.5, 2f64);
+        assert_eq!(2e9f64 + 1.0, r.area());
+        assert_eq!(true, r.contains(Vector2::new(1e9f64 + 0.25, 1f64)));
+
+        let t = tri!(0f64, 0f64, 0.1f64, 0f64, 0f64, 0.1f64);
+        assert_eq!(Vector2::new(0.1f64 / 3.0, 0.1f64 / 3.0), t.center());
+    }
+
+    #[test]
+    fn f64_vertexes() {
+        let r = rec!(0f64, 0f64, 0.1f64, 0.2f64);
+        let (v, _) = r.vertexes();
+        assert_eq!(Vector2::new(0.1f32, 0.2f32), v[2]);
+
+        let r: Rectangle<f64> = rec!(1f32, 2f32, 3f32, 4f32).into();
+        assert_eq!(Some(rec!(1f32, 2f32, 3f32, 4f32)), r.cast());
+    }
+
+    #[test]
+    fn hairline_vertexes() {
+        use hal::pso::Primitive;
+
+        let r = rec!(0f32, 0f32, 1f32, 1f32);
+        assert_eq!(Primitive::TriangleList, r.topology());
+        let r = r.format(ShapeFormat::Hairline);
+        assert_eq!(Primitive::LineList, r.topology());
+        let (v, i) = r.vertexes();
+        assert_eq!(4, v.len());
+        assert_eq!(Some(vec![0, 1, 1, 2, 2, 3, 3, 0]), i);
+
+        let t = tri!(0f32, 0f32, 1f32, 0f32, 0f32, 1f32).format(ShapeFormat::Hairline);
+        assert_eq!(Primitive::LineList, t.topology());
+        assert_eq!(Some(vec![0, 1, 1, 2, 2, 0]), t.vertexes().1);
+    }
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use crate::batch::*;
+    use crate::pipeline::Vertex;
+    use cgmath::Vector2;
+    use rgb::RGBA8;
+
+    fn verts(n: usize) -> Vec<Vertex> {
+        vec![
+            Vertex {
+                a_pos: Vector2::new(0f32, 0f32),
+                a_uv: Vector2::new(0f32, 0f32),
+                a_color: RGBA8::new(0, 0, 0, 0),
+            };
+            n
+        ]
+    }
+
+    #[test]
+    fn merges_same_key() {
+        let mut b = Batcher::new();
+        b.push(0, &verts(4), Some(&[0, 1, 2, 0, 2, 3]));
+        b.push(0, &verts(3), None);
+        b.push(1, &verts(3), None);
+
+        assert_eq!(2, b.batches().len());
+        assert_eq!(&[0, 1, 2, 0, 2, 3, 4, 5, 6, 0, 1, 2], b.indices_u16());
+        assert_eq!(0..9, b.batches()[0].indices);
+        assert_eq!(7, b.batches()[1].base_vertex);
+        assert_eq!(9..12, b.batches()[1].indices);
+    }
+
+    #[test]
+    fn empty_push_adds_nothing() {
+        let mut b = Batcher::new();
+        assert!(b.push(0, &verts(3), None));
+        assert!(!b.push(0, &[], None));
+        assert!(!b.push_u32(1, &[], &[]));
+        assert_eq!(1, b.batches().len());
+    }
+
+    #[test]
+    fn split_breaks_batch() {
+        let mut b = Batcher::new();
+        b.push(0, &verts(3), None);
+        b.split();
+        b.push(0, &verts(3), None);
+        b.push(0, &verts(3), None);
+
+        assert_eq!(2, b.batches().len());
+        assert_eq!(3, b.batches()[1].base_vertex);
+        assert_eq!(6, b.batches()[1].vertex_count);
+    }
+
+    #[test]
+    fn widens_large_batches() {
+        let mut b = Batcher::new();
+        b.push(0, &verts(3), None);
+        for _ in 0..16384 {
+            b.push(1, &verts(4), Some(&[0, 1, 2, 0, 2, 3]));
+        }
+
+        let batches = b.batches();
+        assert_eq!(2, batches.len());
+        assert_eq!(IndexWidth::U16, batches[0].width);
+        assert_eq!(IndexWidth::U32, batches[1].width);
+        assert_eq!(65536, batches[1].vertex_count);
+        assert_eq!(3, b.indices_u16().len());
+        assert_eq!(0..16384 * 6, batches[1].indices);
+        assert_eq!(65535, *b.indices_u32().last().unwrap());
+    }
+
+    #[test]
+    fn u32_indices_narrow_or_widen() {
+        let mut b = Batcher::new();
+        b.push(0, &verts(3), None);
+        b.push_u32(0, &verts(3), &[0, 1, 1, 2, 2, 0]);
+        assert_eq!(1, b.batches().len());
+        assert_eq!(&[0, 1, 2, 3, 4, 4, 5, 5, 3], b.indices_u16());
+
+        let count = 70_000;
+        b.push_u32(0, &verts(count), &[0, count as u32 - 1]);
+        assert_eq!(IndexWidth::U32, b.batches()[0].width);
+        assert_eq!(&[6, 70_005], &b.indices_u32()[9..]);
+    }
+
+    #[test]
+    fn triangle_edges_trace_triangles() {
+        use hal::pso::Primitive;
+
+        assert_eq!(
+            vec![0, 1, 1, 2, 2, 0, 0, 2, 2, 3, 3, 0],
+            triangle_edges(Primitive::TriangleList, &[0, 1, 2, 0, 2, 3])
+        );
+        assert_eq!(
+            vec![0, 1, 1, 2, 2, 0, 1, 2, 2, 3, 3, 1],
+            triangle_edges(Primitive::TriangleStrip, &[0, 1, 2, 3])
+        );
+        assert_eq!(
+            vec![70_000u32, 70_001, 70_001, 70_002, 70_002, 70_000],
+            triangle_edges(Primitive::TriangleList, &[70_000u32, 70_001, 70_002])
+        );
+        assert!(!is_triangles(Primitive::LineList));
+    }
+}
+
+#[cfg(test)]
+mod uniform_tests {
+    use crate::item::UniformBlocks;
+
+    #[test]
+    fn blocks_follow_offset_alignment() {
+        let limits = hal::Limits {
+            min_uniform_buffer_offset_alignment: 256,
+            ..hal::Limits::default()
+        };
+        let mut blocks = UniformBlocks::new(84, limits.min_uniform_buffer_offset_alignment, 2);
+        assert_eq!(0, blocks.push(&[1f32; 21]));
+        assert_eq!(1, blocks.push(&[2f32; 4]));
+        assert_eq!(2, blocks.len());
+        assert_eq!(512, blocks.get_staged().len());
+        assert_eq!(&2f32.to_ne_bytes(), &blocks.get_staged()[256..260]);
+
+        assert!(blocks.reserve());
+        assert!(!blocks.reserve());
+        assert_eq!(2 * 64 * 256, blocks.get_buffer_size());
+        assert_eq!(256, blocks.offset(0, 1));
+        assert_eq!((64 + 1) * 256, blocks.offset(1, 1));
+
+        // Regions grow once the blocks outnumber them.
+        blocks.clear();
+        for _ in 0..65 {
+            blocks.push(&0u32);
+        }
+        assert!(blocks.reserve());
+        assert_eq!(128 * 256, blocks.offset(1, 0));
+        for index in 0..65 {
+            assert_eq!(0, blocks.offset(1, index) % 256);
+        }
+
+        // Without an alignment requirement blocks are packed.
+        let mut packed = UniformBlocks::new(84, 0, 1);
+        packed.push(&0u32);
+        assert_eq!(84, packed.offset(0, 1));
+    }
+}
+
+#[cfg(test)]
+mod golden_tests {
+    use crate::golden::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn compare_counts_pixels_over_tolerance() {
+        let expected = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+        let mut actual = expected.clone();
+        actual.put_pixel(0, 0, Rgba([102, 100, 100, 255]));
+        actual.put_pixel(1, 0, Rgba([103, 100, 100, 255]));
+        actual.put_pixel(2, 0, Rgba([100, 100, 100, 0]));
+
+        let c = compare(&actual, &expected, 2);
+        assert_eq!(2, c.differing_pixels);
+        assert_eq!(Rgba([255, 0, 0, 255]), *c.diff.get_pixel(1, 0));
+        assert_ne!(Rgba([255, 0, 0, 255]), *c.diff.get_pixel(0, 0));
+    }
+}
+
+#[cfg(test)]
+mod camera_tests {
+    use crate::camera::*;
+    use cgmath::{Rad, Vector2, Vector4};
+
+    fn ndc(camera: &Camera2D, x: f32, y: f32) -> Vector2<f32> {
+        let p = camera.view_projection() * Vector4::new(x, y, 0.0, 1.0);
+        Vector2::new(p.x, p.y)
+    }
+
+    fn assert_near(expected: Vector2<f32>, actual: Vector2<f32>) {
+        let d = expected - actual;
+        let eps = 1e-5 * expected.x.abs().max(expected.y.abs()).max(1.0);
+        assert!(
+            d.x.abs() < eps && d.y.abs() < eps,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn pixels_fill_viewport() {
+        let camera = Camera2D::new(200, 100, YAxis::Down);
+        assert_near(Vector2::new(-1.0, -1.0), ndc(&camera, 0.0, 0.0));
+        assert_near(Vector2::new(1.0, 1.0), ndc(&camera, 200.0, 100.0));
+        assert_near(Vector2::new(0.0, 0.0), ndc(&camera, 100.0, 50.0));
+    }
+
+    #[test]
+    fn y_up_starts_bottom_left() {
+        let camera = Camera2D::new(200, 100, YAxis::Up);
+        assert_near(Vector2::new(-1.0, 1.0), ndc(&camera, 0.0, 0.0));
+        assert_near(Vector2::new(1.0, -1.0), ndc(&camera, 200.0, 100.0));
+    }
+
+    #[test]
+    fn zoom_and_rotation() {
+        let mut camera = Camera2D::new(100, 100, YAxis::Down);
+        camera.position = Vector2::new(0.0, 0.0);
+        camera.zoom = 2.0;
+        assert_near(Vector2::new(0.4, 0.0), ndc(&camera, 10.0, 0.0));
+
+        // A quarter turn counterclockwise on screen moves +x to screen up.
+        camera.rotation = Rad(std::f32::consts::FRAC_PI_2);
+        assert_near(Vector2::new(0.0, -0.4), ndc(&camera, 10.0, 0.0));
+
+        camera.y_axis = YAxis::Up;
+        assert_near(Vector2::new(0.0, -0.4), ndc(&camera, 10.0, 0.0));
+        assert_near(Vector2::new(-0.4, 0.0), ndc(&camera, 0.0, 10.0));
+    }
+
+    #[test]
+    fn screen_world_round_trip() {
+        let mut camera = Camera2D::new(300, 200, YAxis::Up);
+        camera.position = Vector2::new(12.0, -7.0);
+        camera.zoom = 3.0;
+        camera.rotation = Rad(0.3);
+
+        let screen = Vector2::new(40.0, 170.0);
+        assert_near(screen, camera.world_to_screen(camera.screen_to_world(screen)));
+        assert_near(camera.position, camera.screen_to_world(Vector2::new(150.0, 100.0)));
+    }
+
+    #[test]
+    fn wheel_zooms_around_cursor() {
+        let mut camera = Camera2D::new(200, 200, YAxis::Down);
+        let mut controller = ViewController::new(0.5, 4.0);
+        controller.easing = 0.0;
+
+        let cursor = Vector2::new(50.0, 150.0);
+        let world = camera.screen_to_world(cursor);
+        controller.cursor_moved(&camera, cursor);
+        controller.scroll(&camera, 100.0);
+        controller.update(&mut camera, 0.0);
+
+        assert_eq!(4.0, camera.zoom);
+        assert_near(world, camera.screen_to_world(cursor));
+    }
+
+    #[test]
+    fn drag_pans_and_eases() {
+        let mut camera = Camera2D::new(200, 200, YAxis::Down);
+        let mut controller = ViewController::default();
+        let start = camera.position;
+
+        controller.cursor_moved(&camera, Vector2::new(100.0, 100.0));
+        controller.set_dragging(true);
+        controller.cursor_moved(&camera, Vector2::new(130.0, 90.0));
+        controller.set_dragging(false);
+
+        controller.update(&mut camera, 0.01);
+        assert!(camera.position.x < start.x && camera.position.x > start.x - 30.0);
+
+        controller.update(&mut camera, 10.0);
+        assert_near(start + Vector2::new(-30.0, 10.0), camera.position);
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use crate::cache::CacheKey;
+
+    const KEY: CacheKey = CacheKey {
+        vendor: 0x10de,
+        device: 0x1c82,
+        name_hash: 42,
+    };
+
+    #[test]
+    fn round_trip() {
+        let bytes = KEY.encode(b"driver blob");
+        assert_eq!(Ok(&b"driver blob"[..]), KEY.decode(&bytes));
+    }
+
+    #[test]
+    fn rejects_other_adapter() {
+        let other = CacheKey { device: 0x1c83, ..KEY };
+        assert!(KEY.decode(&other.encode(b"driver blob")).is_err());
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let bytes = KEY.encode(b"driver blob");
+        assert!(KEY.decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(KEY.decode(&bytes[..10]).is_err());
+
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(KEY.decode(&flipped).is_err());
+    }
+}
+
+#[cfg(test)]
+mod shader_tests {
+    use crate::shader;
+
+    #[test]
+    fn shaders_are_embedded() {
+        for name in &["quad.vert", "quad.frag"] {
+            let spirv = shader::embedded(name).unwrap();
+            assert_eq!(&[0x03, 0x02, 0x23, 0x07], &spirv[..4]);
+        }
+        assert!(shader::embedded("missing.frag").is_none());
+    }
+
+    #[test]
+    fn watcher_reports_changes() {
+        use std::{fs, time::{Duration, SystemTime}};
+
+        let dir = std::env::temp_dir().join(format!("core-shader-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.frag"), "").unwrap();
+
+        let mut watcher = shader::ShaderWatcher::new(&dir);
+        assert!(watcher.poll().is_empty());
+
+        fs::write(dir.join("b.vert"), "").unwrap();
+        let file = fs::File::options().write(true).open(dir.join("a.frag")).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert_eq!(vec!["a.frag", "b.vert"], watcher.poll());
+        assert!(watcher.poll().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_shader_is_an_error() {
+        use crate::pipeline::PipelineError;
+        use hal::pso::ShaderStageFlags;
+        use std::error::Error;
+
+        assert_eq!(Some(ShaderStageFlags::FRAGMENT), shader::stage("quad.frag"));
+        assert_eq!(None, shader::stage("common.glsl"));
+
+        let e = shader::try_load("missing.vert").unwrap_err();
+        assert!(matches!(e, shader::ShaderError::NotFound { .. }), "{:?}", e);
+
+        let e = PipelineError::from(e);
+        assert!(e.to_string().contains("missing.vert"), "{}", e);
+        assert!(e.source().is_some());
+    }
+
+    #[test]
+    fn variants_are_embedded() {
+        use crate::variants::variant_key;
+
+        assert_eq!("quad.frag", variant_key("quad.frag", &[]));
+        assert_eq!("a.frag?A=1&B=2", variant_key("a.frag", &[("B", "2"), ("A", "1")]));
+
+        for (name, defines) in crate::variants::VARIANTS {
+            let spirv = shader::embedded(&variant_key(name, defines)).unwrap();
+            assert_eq!(&[0x03, 0x02, 0x23, 0x07], &spirv[..4]);
+        }
+        assert!(shader::try_load_variant("quad.frag", &[]).is_ok());
+        #[cfg(not(feature = "dev-shaders"))]
+        assert!(matches!(
+            shader::try_load_variant("quad.frag", &[("UNDECLARED", "1")]),
+            Err(shader::ShaderError::NotFound { .. })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod pipeline_tests {
+    use crate::pipeline::Vertex;
+    use crate::vertex::VertexLayout;
+    use std::mem::{offset_of, size_of};
+
+    #[test]
+    fn vertex_attributes_match_layout() {
+        let desc = Vertex::buffer_desc(1);
+        assert_eq!(1, desc.binding);
+        assert_eq!(size_of::<Vertex>() as u32, desc.stride);
+
+        let offsets: Vec<_> = Vertex::attributes(1).iter().map(|a| (a.location, a.binding, a.element.offset)).collect();
+        assert_eq!(
+            vec![
+                (0, 1, offset_of!(Vertex, a_pos) as u32),
+                (1, 1, offset_of!(Vertex, a_uv) as u32),
+                (2, 1, offset_of!(Vertex, a_color) as u32),
+            ],
+            offsets
+        );
+    }
+}
+
+#[cfg(test)]
+mod reflect_tests {
+    use crate::reflect::ShaderReflection;
+    use hal::{format::Format, pso};
+
+    fn op(spirv: &mut Vec<u32>, opcode: u32, args: &[u32]) {
+        spirv.push(((args.len() as u32 + 1) << 16) | opcode);
+        spirv.extend_from_slice(args);
+    }
+
+    fn module(execution_model: u32) -> Vec<u32> {
+        let mut spirv = vec![0x0723_0203, 0x0001_0000, 0, 100, 0];
+        // OpEntryPoint, "main"
+        op(&mut spirv, 15, &[execution_model, 99, u32::from_le_bytes(*b"main"), 0]);
+        spirv
+    }
+
+    /// Like quad.frag: a texture and sampler in set 0, a uniform block in set
+    /// 1, plus a mat4 of push constants.
+    fn fragment() -> Vec<u32> {
+        let mut spirv = module(4);
+        op(&mut spirv, 5, &[5, u32::from_le_bytes(*b"col\0")]);
+        op(&mut spirv, 22, &[1, 32]);
+        op(&mut spirv, 23, &[2, 1, 4]);
+        op(&mut spirv, 30, &[3, 2]);
+        op(&mut spirv, 71, &[3, 2]);
+        op(&mut spirv, 72, &[3, 0, 35, 0]);
+        op(&mut spirv, 32, &[4, 2, 3]);
+        op(&mut spirv, 59, &[4, 5, 2]);
+        op(&mut spirv, 71, &[5, 34, 1]);
+        op(&mut spirv, 71, &[5, 33, 0]);
+        op(&mut spirv, 25, &[6, 1, 1, 0, 0, 0, 1, 0]);
+        op(&mut spirv, 32, &[7, 0, 6]);
+        op(&mut spirv, 59, &[7, 8, 0]);
+        op(&mut spirv, 71, &[8, 34, 0]);
+        op(&mut spirv, 71, &[8, 33, 0]);
+        op(&mut spirv, 26, &[9]);
+        op(&mut spirv, 32, &[10, 0, 9]);
+        op(&mut spirv, 59, &[10, 11, 0]);
+        op(&mut spirv, 71, &[11, 34, 0]);
+        op(&mut spirv, 71, &[11, 33, 1]);
+        op(&mut spirv, 24, &[12, 2, 4]);
+        op(&mut spirv, 30, &[13, 12]);
+        op(&mut spirv, 72, &[13, 0, 35, 0]);
+        op(&mut spirv, 72, &[13, 0, 7, 16]);
+        op(&mut spirv, 32, &[14, 9, 13]);
+        op(&mut spirv, 59, &[14, 15, 9]);
+        spirv
+    }
+
+    fn binding(binding: u32, ty: pso::DescriptorType) -> pso::DescriptorSetLayoutBinding {
+        pso::DescriptorSetLayoutBinding {
+            binding,
+            ty,
+            count: 1,
+            stage_flags: pso::ShaderStageFlags::FRAGMENT,
+            immutable_samplers: false,
+        }
+    }
+
+    #[test]
+    fn derives_set_layouts() {
+        let reflection = ShaderReflection::new(&fragment()).unwrap();
+        assert_eq!(pso::ShaderStageFlags::FRAGMENT, reflection.stages);
+        assert_eq!(Some("col"), reflection.bindings[2].name.as_deref());
+
+        let image = pso::DescriptorType::Image {
+            ty: pso::ImageDescriptorType::Sampled { with_sampler: false },
+        };
+        let uniform = pso::DescriptorType::Buffer {
+            ty: pso::BufferDescriptorType::Uniform,
+            format: pso::BufferDescriptorFormat::Structured { dynamic_offset: false },
+        };
+        let textures = vec![binding(0, image), binding(1, pso::DescriptorType::Sampler)];
+        let uniforms = vec![binding(0, uniform)];
+        // The hal binding type has no PartialEq.
+        assert_eq!(format!("{:?}", textures), format!("{:?}", reflection.set_bindings(0)));
+        assert_eq!(format!("{:?}", uniforms), format!("{:?}", reflection.set_bindings(1)));
+        assert_eq!(vec![(pso::ShaderStageFlags::FRAGMENT, 0..64)], reflection.push_constants);
+
+        assert!(reflection.check_bindings(&[&textures, &uniforms]).is_ok());
+        assert!(reflection.check_bindings(&[&textures]).is_err());
+        assert!(reflection.check_bindings(&[&uniforms, &textures]).is_err());
+
+        let all = pso::ShaderStageFlags::ALL;
+        assert!(reflection.check_push_constants(&[(all, 0..64)]).is_ok());
+        assert!(reflection.check_push_constants(&[(all, 0..16)]).is_err());
+        assert!(reflection.check_push_constants(&[(pso::ShaderStageFlags::VERTEX, 0..64)]).is_err());
+    }
+
+    #[test]
+    fn checks_vertex_inputs() {
+        let mut spirv = module(0);
+        op(&mut spirv, 22, &[1, 32]);
+        op(&mut spirv, 23, &[2, 1, 2]);
+        op(&mut spirv, 32, &[3, 1, 2]);
+        op(&mut spirv, 59, &[3, 4, 1]);
+        op(&mut spirv, 71, &[4, 30, 1]);
+
+        let reflection = ShaderReflection::new(&spirv).unwrap();
+        assert_eq!(vec![(1, Format::Rg32Sfloat)], reflection.inputs);
+
+        let attribute = |format| pso::AttributeDesc {
+            location: 1,
+            binding: 0,
+            element: pso::Element { format, offset: 0 },
+        };
+        assert!(reflection.check_inputs(&[attribute(Format::Rgba8Unorm)]).is_ok());
+        assert!(reflection.check_inputs(&[attribute(Format::Rg32Uint)]).is_err());
+        assert!(reflection.check_inputs(&[]).is_err());
+    }
+
+    #[test]
+    fn empty_module_uses_nothing() {
+        let reflection = ShaderReflection::new(&[0x0723_0203, 0x0001_0000, 0, 1, 0]).unwrap();
+        assert!(reflection.bindings.is_empty() && reflection.inputs.is_empty());
+        assert!(ShaderReflection::new(&[1, 2, 3]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod preprocess_tests {
+    use crate::preprocess::{Origin, Preprocessor};
+    use std::{fs, path::PathBuf};
+
+    /// A fresh directory holding `files`.
+    fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("core-preprocess-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, code) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, code).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn includes_and_defines() {
+        let dir = dir(
+            "includes",
+            &[
+                ("main.frag", "#version 450\n#include \"color.glsl\"\n#include <lib/sdf.glsl>\nvoid main() {}\n"),
+                ("color.glsl", "#pragma once\nvec3 srgb(vec3 c);\n"),
+                ("lib/sdf.glsl", "#include \"../color.glsl\"\nfloat circle(vec2 p);\n"),
+            ],
+        );
+
+        let dir = fs::canonicalize(dir).unwrap();
+        let source = Preprocessor::new()
+            .define("SAMPLES", "4")
+            .process_file(dir.join("main.frag"))
+            .unwrap();
+        assert_eq!(
+            "#version 450\n#define SAMPLES 4\nvec3 srgb(vec3 c);\nfloat circle(vec2 p);\nvoid main() {}\n",
+            source.code
+        );
+        assert_eq!(Some(&Origin::Define), source.origin(2));
+        assert_eq!(Some(&Origin::File(dir.join("color.glsl"), 2)), source.origin(3));
+        assert_eq!(Some(&Origin::File(dir.join("lib/sdf.glsl"), 2)), source.origin(4));
+        assert_eq!(Some(&Origin::File(dir.join("main.frag"), 4)), source.origin(5));
+
+        let log = source.map_log("ERROR: /tmp/x/0.frag:4: 'p' : undeclared identifier\nERROR: 1 compilation errors.");
+        assert_eq!(
+            format!(
+                "ERROR: {}:2: 'p' : undeclared identifier\nERROR: 1 compilation errors.",
+                dir.join("lib/sdf.glsl").display()
+            ),
+            log
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn search_path_and_errors() {
+        let dir = dir(
+            "errors",
+            &[
+                ("shaders/a.vert", "#include \"common.glsl\"\n"),
+                ("include/common.glsl", "// shared\n"),
+                ("shaders/loop.vert", "#include \"loop.vert\"\n"),
+                ("shaders/missing.vert", "\n#include \"nowhere.glsl\"\n"),
+            ],
+        );
+        let preprocessor = Preprocessor::new().include_dir(dir.join("include"));
+
+        assert_eq!("// shared\n", preprocessor.process_file(dir.join("shaders/a.vert")).unwrap().code);
+        assert!(preprocessor.process_file(dir.join("shaders/loop.vert")).unwrap_err().contains("includes itself"));
+        let missing = preprocessor.process_file(dir.join("shaders/missing.vert")).unwrap_err();
+        assert!(missing.contains("missing.vert:2"), "{}", missing);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pragma_once_root() {
+        let dir = dir(
+            "once",
+            &[
+                ("main.glsl", "#pragma once\nint x;\n#include \"lib/back.glsl\"\n"),
+                ("lib/back.glsl", "#include \"../main.glsl\"\nint y;\n"),
+            ],
+        );
+
+        let source = Preprocessor::new().process_file(dir.join("lib/../main.glsl")).unwrap();
+        assert_eq!("int x;\nint y;\n", source.code);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
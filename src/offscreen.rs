@@ -0,0 +1,222 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+};
+
+use hal::{
+    buffer, command,
+    format::{self as f, AsFormat},
+    image as i, memory as m,
+    prelude::*,
+    pso,
+    Backend,
+};
+
+use crate::adapter::AdapterState;
+use crate::buffer::BufferState;
+use crate::device::DeviceState;
+use crate::item::ColorFormat;
+
+/// A color image rendered into instead of a swapchain image, together with
+/// a host-visible buffer to read it back through.
+pub struct OffscreenState<B: Backend> {
+    image: Option<B::Image>,
+    memory: Option<B::Memory>,
+    image_view: Option<B::ImageView>,
+    readback: BufferState<B>,
+    extent: i::Extent,
+    row_pitch: u32,
+    device: Rc<RefCell<DeviceState<B>>>,
+}
+
+impl<B: Backend> OffscreenState<B> {
+    pub const FORMAT: f::Format = ColorFormat::SELF;
+
+    pub unsafe fn new(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        adapter: &AdapterState<B>,
+        extent: i::Extent,
+    ) -> Self {
+        let (row_pitch, readback) = readback_buffer(&device_ptr, adapter, extent);
+
+        let (image, memory, image_view) = {
+            let device = &device_ptr.borrow().device;
+
+            let kind = i::Kind::D2(extent.width as i::Size, extent.height as i::Size, 1, 1);
+            let mut image = device
+                .create_image(
+                    kind,
+                    1,
+                    Self::FORMAT,
+                    i::Tiling::Optimal,
+                    i::Usage::COLOR_ATTACHMENT | i::Usage::TRANSFER_SRC,
+                    i::ViewCapabilities::empty(),
+                )
+                .unwrap();
+            let req = device.get_image_requirements(&image);
+
+            let device_type = adapter
+                .memory_types
+                .iter()
+                .enumerate()
+                .position(|(id, memory_type)| {
+                    req.type_mask & (1 << id) != 0
+                        && memory_type.properties.contains(m::Properties::DEVICE_LOCAL)
+                })
+                .unwrap()
+                .into();
+
+            let memory = device.allocate_memory(device_type, req.size).unwrap();
+            device.bind_image_memory(&memory, 0, &mut image).unwrap();
+
+            let image_view = device
+                .create_image_view(
+                    &image,
+                    i::ViewKind::D2,
+                    Self::FORMAT,
+                    f::Swizzle::NO,
+                    i::SubresourceRange {
+                        aspects: f::Aspects::COLOR,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+
+            (image, memory, image_view)
+        };
+
+        OffscreenState {
+            image: Some(image),
+            memory: Some(memory),
+            image_view: Some(image_view),
+            readback,
+            extent,
+            row_pitch,
+            device: device_ptr,
+        }
+    }
+
+    pub fn get_view(&self) -> &B::ImageView {
+        self.image_view.as_ref().unwrap()
+    }
+
+    pub fn extent(&self) -> i::Extent {
+        self.extent
+    }
+
+    /// Copies the image back to the host.
+    ///
+    /// The image must have been rendered to and left in
+    /// `Layout::TransferSrcOptimal`, and no other work may be in flight.
+    pub unsafe fn read_pixels(&mut self) -> image::RgbaImage {
+        read_image(
+            &self.device,
+            self.image.as_ref().unwrap(),
+            i::Layout::TransferSrcOptimal,
+            (pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT, i::Access::COLOR_ATTACHMENT_WRITE),
+            self.extent,
+            self.row_pitch,
+            &mut self.readback,
+        )
+    }
+}
+
+/// Row pitch and a host-visible buffer big enough to read back an RGBA8
+/// image of `extent`.
+pub(crate) unsafe fn readback_buffer<B: Backend>(
+    device_ptr: &Rc<RefCell<DeviceState<B>>>,
+    adapter: &AdapterState<B>,
+    extent: i::Extent,
+) -> (u32, BufferState<B>) {
+    let row_alignment_mask = adapter.limits.optimal_buffer_copy_pitch_alignment as u32 - 1;
+    let row_pitch = (extent.width * 4 + row_alignment_mask) & !row_alignment_mask;
+
+    let readback = BufferState::with_capacity(
+        Rc::clone(device_ptr),
+        (row_pitch * extent.height) as u64,
+        buffer::Usage::TRANSFER_DST,
+        &adapter.memory_types,
+    );
+    (row_pitch, readback)
+}
+
+/// Copies an RGBA8 `image` in `layout`, last written by `written`, into
+/// `readback` and returns its pixels. Blocks until the copy is done.
+pub(crate) unsafe fn read_image<B: Backend>(
+    device: &Rc<RefCell<DeviceState<B>>>,
+    image: &B::Image,
+    layout: i::Layout,
+    written: (pso::PipelineStage, i::Access),
+    extent: i::Extent,
+    row_pitch: u32,
+    readback: &mut BufferState<B>,
+) -> image::RgbaImage {
+    let range = i::SubresourceRange {
+        aspects: f::Aspects::COLOR,
+        ..Default::default()
+    };
+
+    device.borrow_mut().submit_once(|cmd_buffer| {
+        cmd_buffer.pipeline_barrier(
+            written.0..pso::PipelineStage::TRANSFER,
+            m::Dependencies::empty(),
+            &[m::Barrier::Image {
+                states: (written.1, layout)..(i::Access::TRANSFER_READ, layout),
+                target: image,
+                families: None,
+                range: range.clone(),
+            }],
+        );
+
+        cmd_buffer.copy_image_to_buffer(
+            image,
+            layout,
+            readback.get_buffer(),
+            &[command::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_width: row_pitch / 4,
+                buffer_height: extent.height,
+                image_layers: i::SubresourceLayers {
+                    aspects: f::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: i::Offset { x: 0, y: 0, z: 0 },
+                image_extent: extent,
+            }],
+        );
+
+        cmd_buffer.pipeline_barrier(
+            pso::PipelineStage::TRANSFER..pso::PipelineStage::HOST,
+            m::Dependencies::empty(),
+            &[m::Barrier::Buffer {
+                states: buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                target: readback.get_buffer(),
+                families: None,
+                range: buffer::SubRange::WHOLE,
+            }],
+        );
+    });
+
+    let mut data = vec![0u8; (row_pitch * extent.height) as usize];
+    readback.read_data(0, &mut data);
+
+    let row_size = (extent.width * 4) as usize;
+    let mut pixels = Vec::with_capacity(row_size * extent.height as usize);
+    for row in data.chunks(row_pitch as usize) {
+        pixels.extend_from_slice(&row[..row_size]);
+    }
+
+    image::RgbaImage::from_raw(extent.width, extent.height, pixels).unwrap()
+}
+
+impl<B: Backend> Drop for OffscreenState<B> {
+    fn drop(&mut self) {
+        let device = &self.device.borrow().device;
+        unsafe {
+            device.destroy_image_view(self.image_view.take().unwrap());
+            device.destroy_image(self.image.take().unwrap());
+            device.free_memory(self.memory.take().unwrap());
+        }
+    }
+}
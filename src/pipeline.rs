@@ -1,11 +1,14 @@
 use std::{
     cell::RefCell,
-    fs,
+    collections::{HashMap, HashSet},
+    fmt,
     mem::size_of,
+    ops::Range,
     rc::Rc,
 };
 
 use hal::{
+    device::{OutOfMemory, ShaderError as ModuleError},
     format::self as f,
     pass,
     prelude::*,
@@ -13,16 +16,199 @@ use hal::{
     Backend,
 };
 
-use cgmath::Vector2;
+use cgmath::{Matrix4, SquareMatrix, Vector2};
 
+use rgb::RGBA8;
+
+use crate::desc::DescSetLayout;
 use crate::device::DeviceState;
+use crate::reflect::ShaderReflection;
+use crate::shader::{self, ShaderError};
+use crate::vertex::VertexLayout;
+
+pub(crate) const ENTRY_NAME: &str = "main";
 
-const ENTRY_NAME: &str = "main";
+/// Why a pipeline couldn't be built.
+#[derive(Debug)]
+pub enum PipelineError {
+    /// A shader didn't load or compile.
+    Shader(ShaderError),
+    /// A shader's SPIR-V couldn't be reflected.
+    Reflect(String),
+    /// The shaders read something the pipeline doesn't provide.
+    Interface(String),
+    Layout(OutOfMemory),
+    /// The device rejected a shader module.
+    ShaderModule {
+        stage: pso::ShaderStageFlags,
+        error: ModuleError,
+    },
+    Creation(pso::CreationError),
+    /// No descriptor set could be allocated for the pipeline.
+    Allocation(pso::AllocationError),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::Shader(e) => write!(f, "{}", e),
+            PipelineError::Reflect(message) => write!(f, "can't reflect shader: {}", message),
+            PipelineError::Interface(message) => write!(f, "{}", message),
+            PipelineError::Layout(e) => write!(f, "can't create pipeline layout: {}", e),
+            PipelineError::ShaderModule { stage, error } => {
+                write!(f, "can't create {:?} shader module: {}", stage, error)
+            }
+            PipelineError::Creation(e) => write!(f, "can't create pipeline: {}", e),
+            PipelineError::Allocation(e) => write!(f, "can't allocate descriptor set: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PipelineError::Shader(e) => Some(e),
+            PipelineError::Layout(e) => Some(e),
+            PipelineError::ShaderModule { error, .. } => Some(error),
+            PipelineError::Creation(e) => Some(e),
+            PipelineError::Allocation(e) => Some(e),
+            PipelineError::Reflect(_) | PipelineError::Interface(_) => None,
+        }
+    }
+}
+
+impl From<ShaderError> for PipelineError {
+    fn from(e: ShaderError) -> Self {
+        PipelineError::Shader(e)
+    }
+}
+
+/// Push constant bytes visible to the vertex stage: the camera's
+/// view-projection matrix.
+pub const PUSH_CONSTANTS_SIZE: u32 = 64;
+
+/// How drawn pixels are combined with what is already in the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    /// Regular transparency.
+    #[default]
+    Alpha,
+    /// Transparency for colors already multiplied by their alpha. The quad
+    /// shaders don't premultiply, so textures and shape colors must be.
+    Premultiplied,
+    /// Adds the color, weighted by alpha, to the target.
+    Additive,
+    /// Multiplies the target by the color, darkening it. Ignores alpha.
+    Multiply,
+    /// Inverse of multiply, brightening the target.
+    Screen,
+    /// Overwrites the target, alpha included.
+    Replace,
+}
 
-#[derive(Debug, Clone, Copy)]
+impl BlendMode {
+    pub fn blend_state(self) -> Option<pso::BlendState> {
+        use pso::{BlendOp, BlendState, Factor};
+
+        match self {
+            BlendMode::Alpha => Some(BlendState::ALPHA),
+            BlendMode::Premultiplied => Some(BlendState::PREMULTIPLIED_ALPHA),
+            BlendMode::Additive => Some(BlendState {
+                color: BlendOp::Add {
+                    src: Factor::SrcAlpha,
+                    dst: Factor::One,
+                },
+                alpha: BlendOp::Add {
+                    src: Factor::Zero,
+                    dst: Factor::One,
+                },
+            }),
+            BlendMode::Multiply => Some(BlendState::MULTIPLY),
+            BlendMode::Screen => Some(BlendState {
+                color: BlendOp::Add {
+                    src: Factor::OneMinusDstColor,
+                    dst: Factor::One,
+                },
+                alpha: BlendOp::PREMULTIPLIED_ALPHA,
+            }),
+            BlendMode::Replace => None,
+        }
+    }
+}
+
+/// A vertex and fragment shader pair, by file name in `src/shaders`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderSet {
+    pub vertex: &'static str,
+    pub fragment: &'static str,
+}
+
+impl ShaderSet {
+    /// Interface of both stages, read from their SPIR-V.
+    pub fn reflect(&self) -> Result<ShaderReflection, PipelineError> {
+        let vertex = ShaderReflection::new(&shader::try_load(self.vertex)?).map_err(PipelineError::Reflect)?;
+        let fragment = ShaderReflection::new(&shader::try_load(self.fragment)?).map_err(PipelineError::Reflect)?;
+        Ok(vertex.merge(fragment))
+    }
+}
+
+/// The shaders drawing shapes and images.
+pub const QUAD_SHADERS: ShaderSet = ShaderSet {
+    vertex: "quad.vert",
+    fragment: "quad.frag",
+};
+
+/// Everything that tells apart the pipelines of one render pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub blend: BlendMode,
+    pub topology: pso::Primitive,
+    pub shaders: ShaderSet,
+    /// Rasterize polygons as their edges. Needs
+    /// `Features::NON_FILL_POLYGON_MODE`.
+    pub wireframe: bool,
+}
+
+impl Default for PipelineKey {
+    fn default() -> Self {
+        PipelineKey {
+            blend: BlendMode::default(),
+            topology: pso::Primitive::TriangleList,
+            shaders: QUAD_SHADERS,
+            wireframe: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, VertexLayout)]
+#[vertex(crate = "crate")]
 pub struct Vertex {
     pub a_pos: Vector2<f32>,
     pub a_uv: Vector2<f32>,
+    pub a_color: RGBA8,
+}
+
+/// The per-draw block of the quad shaders, `Draw` in `draw.glsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawUniforms {
+    /// Applied to vertex positions before the camera.
+    pub transform: Matrix4<f32>,
+    /// Multiplies the color of every fragment.
+    pub tint: [f32; 4],
+    /// Not used by the quad shaders, for custom ones to animate with.
+    pub time: f32,
+}
+
+impl Default for DrawUniforms {
+    fn default() -> Self {
+        DrawUniforms {
+            transform: Matrix4::identity(),
+            tint: [1.0; 4],
+            time: 0.0,
+        }
+    }
 }
 
 pub struct PipelineState<B: Backend> {
@@ -32,117 +218,266 @@ pub struct PipelineState<B: Backend> {
 }
 
 impl<B: Backend> PipelineState<B> {
-    pub unsafe fn new<IS>(
-        desc_layouts: IS,
+    /// Builds the pipeline for `key`, or reports shaders that don't load or
+    /// a pipeline the device refuses.
+    pub unsafe fn new(
+        desc_layouts: &[&DescSetLayout<B>],
         render_pass: &B::RenderPass,
+        key: PipelineKey,
+        cache: Option<&B::PipelineCache>,
         device_ptr: Rc<RefCell<DeviceState<B>>>,
-    ) -> Self
-    where
-        IS: IntoIterator,
-        IS::Item: std::borrow::Borrow<B::DescriptorSetLayout>,
-        IS::IntoIter: ExactSizeIterator,
-    {
+    ) -> Result<Self, PipelineError> {
+        PipelineBuilder::new(shader::try_load(key.shaders.vertex)?)
+            .fragment_shader(shader::try_load(key.shaders.fragment)?)
+            .vertex_specialization(hal::spec_const_list![1.0f32])
+            .vertex_type::<Vertex>(0)
+            .topology(key.topology)
+            .rasterizer(pso::Rasterizer {
+                polygon_mode: if key.wireframe {
+                    pso::PolygonMode::Line
+                } else {
+                    pso::PolygonMode::Fill
+                },
+                ..pso::Rasterizer::FILL
+            })
+            .blend(key.blend.blend_state())
+            .desc_set_layouts(desc_layouts)
+            .push_constants(pso::ShaderStageFlags::VERTEX, 0..PUSH_CONSTANTS_SIZE)
+            .build(render_pass, cache, device_ptr)
+    }
+}
+
+/// Describes a graphics pipeline piece by piece, for shaders other than the
+/// built-in ones.
+///
+/// Everything but the vertex shader is optional. By default there is no
+/// vertex input, triangles are filled and one color target is alpha blended.
+pub struct PipelineBuilder<'a, B: Backend> {
+    vertex_spirv: Vec<u32>,
+    fragment_spirv: Option<Vec<u32>>,
+    vertex_specialization: pso::Specialization<'static>,
+    fragment_specialization: pso::Specialization<'static>,
+    vertex_buffers: Vec<pso::VertexBufferDesc>,
+    attributes: Vec<pso::AttributeDesc>,
+    topology: pso::Primitive,
+    rasterizer: pso::Rasterizer,
+    blend_targets: Vec<pso::ColorBlendDesc>,
+    desc_layouts: Vec<&'a B::DescriptorSetLayout>,
+    /// Bindings of each layout, if known, to check the shaders against.
+    layout_bindings: Vec<Option<&'a [pso::DescriptorSetLayoutBinding]>>,
+    push_constants: Vec<(pso::ShaderStageFlags, Range<u32>)>,
+    subpass: pass::SubpassId,
+}
+
+impl<'a, B: Backend> PipelineBuilder<'a, B> {
+    pub fn new(vertex_spirv: Vec<u32>) -> Self {
+        PipelineBuilder {
+            vertex_spirv,
+            fragment_spirv: None,
+            vertex_specialization: pso::Specialization::default(),
+            fragment_specialization: pso::Specialization::default(),
+            vertex_buffers: Vec::new(),
+            attributes: Vec::new(),
+            topology: pso::Primitive::TriangleList,
+            rasterizer: pso::Rasterizer::FILL,
+            blend_targets: vec![pso::ColorBlendDesc {
+                mask: pso::ColorMask::ALL,
+                blend: Some(pso::BlendState::ALPHA),
+            }],
+            desc_layouts: Vec::new(),
+            layout_bindings: Vec::new(),
+            push_constants: Vec::new(),
+            subpass: 0,
+        }
+    }
+
+    pub fn fragment_shader(mut self, spirv: Vec<u32>) -> Self {
+        self.fragment_spirv = Some(spirv);
+        self
+    }
+
+    pub fn vertex_specialization(mut self, specialization: pso::Specialization<'static>) -> Self {
+        self.vertex_specialization = specialization;
+        self
+    }
+
+    pub fn fragment_specialization(mut self, specialization: pso::Specialization<'static>) -> Self {
+        self.fragment_specialization = specialization;
+        self
+    }
+
+    /// Adds a vertex buffer binding along with the attributes read from it.
+    pub fn vertex_buffer(mut self, buffer: pso::VertexBufferDesc, attributes: Vec<pso::AttributeDesc>) -> Self {
+        self.vertex_buffers.push(buffer);
+        self.attributes.extend(attributes);
+        self
+    }
+
+    /// Adds a vertex buffer binding holding `V`s.
+    pub fn vertex_type<V: VertexLayout>(self, binding: pso::BufferIndex) -> Self {
+        self.vertex_buffer(V::buffer_desc(binding), V::attributes(binding))
+    }
+
+    pub fn topology(mut self, topology: pso::Primitive) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn rasterizer(mut self, rasterizer: pso::Rasterizer) -> Self {
+        self.rasterizer = rasterizer;
+        self
+    }
+
+    /// Replaces the color targets with a single one using `blend`.
+    pub fn blend(self, blend: Option<pso::BlendState>) -> Self {
+        self.blend_targets(vec![pso::ColorBlendDesc {
+            mask: pso::ColorMask::ALL,
+            blend,
+        }])
+    }
+
+    /// One entry per color attachment of the subpass.
+    pub fn blend_targets(mut self, targets: Vec<pso::ColorBlendDesc>) -> Self {
+        self.blend_targets = targets;
+        self
+    }
+
+    /// Appends raw layouts. The shaders can't be checked against them, prefer
+    /// `desc_set_layouts`.
+    pub fn desc_layouts(mut self, layouts: &[&'a B::DescriptorSetLayout]) -> Self {
+        self.desc_layouts.extend_from_slice(layouts);
+        self.layout_bindings.extend(layouts.iter().map(|_| None));
+        self
+    }
+
+    pub fn desc_set_layouts(mut self, layouts: &[&'a DescSetLayout<B>]) -> Self {
+        for layout in layouts {
+            self.desc_layouts.push(layout.get_layout());
+            self.layout_bindings.push(Some(layout.get_bindings()));
+        }
+        self
+    }
+
+    pub fn push_constants(mut self, stages: pso::ShaderStageFlags, range: Range<u32>) -> Self {
+        self.push_constants.push((stages, range));
+        self
+    }
+
+    pub fn subpass(mut self, subpass: pass::SubpassId) -> Self {
+        self.subpass = subpass;
+        self
+    }
+
+    /// Reflects the shaders and checks that this pipeline provides everything
+    /// they read.
+    pub fn check_shaders(&self) -> Result<ShaderReflection, PipelineError> {
+        let mut reflection = ShaderReflection::new(&self.vertex_spirv).map_err(PipelineError::Reflect)?;
+        if let Some(fragment) = &self.fragment_spirv {
+            reflection = reflection.merge(ShaderReflection::new(fragment).map_err(PipelineError::Reflect)?);
+        }
+
+        reflection.check_inputs(&self.attributes).map_err(PipelineError::Interface)?;
+        reflection
+            .check_push_constants(&self.push_constants)
+            .map_err(PipelineError::Interface)?;
+        let bindings: Option<Vec<_>> = self.layout_bindings.iter().copied().collect();
+        if let Some(bindings) = bindings {
+            reflection.check_bindings(&bindings).map_err(PipelineError::Interface)?;
+        }
+        Ok(reflection)
+    }
+
+    pub unsafe fn build(
+        self,
+        render_pass: &B::RenderPass,
+        cache: Option<&B::PipelineCache>,
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+    ) -> Result<PipelineState<B>, PipelineError> {
+        self.check_shaders()?;
+
         let device = &device_ptr.borrow().device;
+
         let pipeline_layout = device
-            .create_pipeline_layout(desc_layouts, &[(pso::ShaderStageFlags::VERTEX, 0..8)])
-            .expect("Can't create pipeline layout");
+            .create_pipeline_layout(self.desc_layouts.iter().copied(), &self.push_constants)
+            .map_err(PipelineError::Layout)?;
+
+        let vs_module = match device.create_shader_module(&self.vertex_spirv) {
+            Ok(module) => module,
+            Err(error) => {
+                device.destroy_pipeline_layout(pipeline_layout);
+                return Err(PipelineError::ShaderModule {
+                    stage: pso::ShaderStageFlags::VERTEX,
+                    error,
+                });
+            }
+        };
+        let fs_module = match self.fragment_spirv.as_ref().map(|spirv| device.create_shader_module(spirv)) {
+            Some(Err(error)) => {
+                device.destroy_shader_module(vs_module);
+                device.destroy_pipeline_layout(pipeline_layout);
+                return Err(PipelineError::ShaderModule {
+                    stage: pso::ShaderStageFlags::FRAGMENT,
+                    error,
+                });
+            }
+            fs_module => fs_module.map(Result::unwrap),
+        };
 
         let pipeline = {
-            let vs_module = {
-                let glsl = fs::read_to_string("src/bin/data/quad.vert").unwrap();
-                let file =
-                    glsl_to_spirv::compile(&glsl, glsl_to_spirv::ShaderType::Vertex).unwrap();
-                let spirv: Vec<u32> = auxil::read_spirv(file).unwrap();
-                device.create_shader_module(&spirv).unwrap()
+            let vs_entry = pso::EntryPoint::<B> {
+                entry: ENTRY_NAME,
+                module: &vs_module,
+                specialization: self.vertex_specialization.clone(),
             };
-            let fs_module = {
-                let glsl = fs::read_to_string("src/bin/data/quad.frag").unwrap();
-                let file =
-                    glsl_to_spirv::compile(&glsl, glsl_to_spirv::ShaderType::Fragment).unwrap();
-                let spirv: Vec<u32> = auxil::read_spirv(file).unwrap();
-                device.create_shader_module(&spirv).unwrap()
+            let fs_entry = fs_module.as_ref().map(|module| pso::EntryPoint::<B> {
+                entry: ENTRY_NAME,
+                module,
+                specialization: self.fragment_specialization.clone(),
+            });
+
+            let subpass = pass::Subpass {
+                index: self.subpass,
+                main_pass: render_pass,
             };
 
-            let pipeline = {
-                let (vs_entry, fs_entry) = (
-                    pso::EntryPoint::<B> {
-                        entry: ENTRY_NAME,
-                        module: &vs_module,
-                        specialization: hal::spec_const_list![0.8f32],
-                    },
-                    pso::EntryPoint::<B> {
-                        entry: ENTRY_NAME,
-                        module: &fs_module,
-                        specialization: pso::Specialization::default(),
-                    },
-                );
-
-                let subpass = pass::Subpass {
-                    index: 0,
-                    main_pass: render_pass,
-                };
-
-                let vertex_buffers = vec![pso::VertexBufferDesc {
-                    binding: 0,
-                    stride: size_of::<Vertex>() as u32,
-                    rate: pso::VertexInputRate::Vertex,
-                }];
-
-                let attributes = vec![
-                    pso::AttributeDesc {
-                        location: 0,
-                        binding: 0,
-                        element: pso::Element {
-                            format: f::Format::Rg32Sfloat,
-                            offset: 0,
-                        },
+            let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+                pso::PrimitiveAssemblerDesc::Vertex {
+                    buffers: &self.vertex_buffers,
+                    attributes: &self.attributes,
+                    input_assembler: pso::InputAssemblerDesc {
+                        primitive: self.topology,
+                        with_adjacency: false,
+                        restart_index: None,
                     },
-                    pso::AttributeDesc {
-                        location: 1,
-                        binding: 0,
-                        element: pso::Element {
-                            format: f::Format::Rg32Sfloat,
-                            offset: 8,
-                        },
-                    },
-                ];
-
-                let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
-                    pso::PrimitiveAssemblerDesc::Vertex {
-                        buffers: &vertex_buffers,
-                        attributes: &attributes,
-                        input_assembler: pso::InputAssemblerDesc {
-                            primitive: pso::Primitive::TriangleList,
-                            with_adjacency: false,
-                            restart_index: None,
-                        },
-                        vertex: vs_entry,
-                        geometry: None,
-                        tessellation: None,
-                    },
-                    pso::Rasterizer::FILL,
-                    Some(fs_entry),
-                    &pipeline_layout,
-                    subpass,
-                );
-                pipeline_desc.blender.targets.push(pso::ColorBlendDesc {
-                    mask: pso::ColorMask::ALL,
-                    blend: Some(pso::BlendState::ALPHA),
-                });
+                    vertex: vs_entry,
+                    geometry: None,
+                    tessellation: None,
+                },
+                self.rasterizer,
+                fs_entry,
+                &pipeline_layout,
+                subpass,
+            );
+            pipeline_desc.blender.targets = self.blend_targets.clone();
 
-                device.create_graphics_pipeline(&pipeline_desc, None)
-            };
+            device.create_graphics_pipeline(&pipeline_desc, cache)
+        };
 
-            device.destroy_shader_module(vs_module);
+        device.destroy_shader_module(vs_module);
+        if let Some(fs_module) = fs_module {
             device.destroy_shader_module(fs_module);
+        }
 
-            pipeline.unwrap()
-        };
-
-        PipelineState {
-            pipeline: Some(pipeline),
-            pipeline_layout: Some(pipeline_layout),
-            device: Rc::clone(&device_ptr),
+        match pipeline {
+            Ok(pipeline) => Ok(PipelineState {
+                pipeline: Some(pipeline),
+                pipeline_layout: Some(pipeline_layout),
+                device: Rc::clone(&device_ptr),
+            }),
+            Err(e) => {
+                device.destroy_pipeline_layout(pipeline_layout);
+                Err(PipelineError::Creation(e))
+            }
         }
     }
 }
@@ -155,4 +490,121 @@ impl<B: Backend> Drop for PipelineState<B> {
             device.destroy_pipeline_layout(self.pipeline_layout.take().unwrap());
         }
     }
-}
\ No newline at end of file
+}
+
+/// Pipelines for one render pass, built the first time they're asked for.
+pub struct PipelineCache<B: Backend> {
+    pipelines: HashMap<PipelineKey, PipelineState<B>>,
+    /// The last failed build of each key, until it builds again.
+    errors: HashMap<PipelineKey, PipelineError>,
+    device: Rc<RefCell<DeviceState<B>>>,
+}
+
+impl<B: Backend> PipelineCache<B> {
+    pub fn new(device: Rc<RefCell<DeviceState<B>>>) -> Self {
+        PipelineCache {
+            pipelines: HashMap::new(),
+            errors: HashMap::new(),
+            device,
+        }
+    }
+
+    /// Builds the pipeline for `key` unless it exists already.
+    ///
+    /// A key that failed to build isn't tried again until `reload` or
+    /// `clear`, its error is returned instead.
+    pub unsafe fn prepare(
+        &mut self,
+        key: PipelineKey,
+        desc_layouts: &[&DescSetLayout<B>],
+        render_pass: &B::RenderPass,
+        cache: Option<&B::PipelineCache>,
+    ) -> Result<(), &PipelineError> {
+        if self.pipelines.contains_key(&key) {
+            return Ok(());
+        }
+        if !self.errors.contains_key(&key) {
+            match PipelineState::new(desc_layouts, render_pass, key, cache, Rc::clone(&self.device)) {
+                Ok(pipeline) => {
+                    self.pipelines.insert(key, pipeline);
+                    return Ok(());
+                }
+                Err(e) => {
+                    log::error!("Can't build pipeline {:?}: {}", key, e);
+                    self.errors.insert(key, e);
+                }
+            }
+        }
+        Err(&self.errors[&key])
+    }
+
+    /// Rebuilds every pipeline using one of the `changed` shader files,
+    /// including ones that failed to build before.
+    ///
+    /// A pipeline that fails to build is kept as it was and the error logged
+    /// and remembered.
+    /// The caller must make sure none of them is still in use by the GPU.
+    pub unsafe fn reload(
+        &mut self,
+        changed: &[String],
+        desc_layouts: &[&DescSetLayout<B>],
+        render_pass: &B::RenderPass,
+        cache: Option<&B::PipelineCache>,
+    ) {
+        // Any shader may include a changed file.
+        let include_changed = changed.iter().any(|name| !shader::is_stage(name));
+        // A key can be in both maps while a rebuild after a good build fails.
+        let keys: HashSet<PipelineKey> =
+            self.pipelines.keys().chain(self.errors.keys()).copied().collect();
+        for key in keys {
+            let shaders = [key.shaders.vertex, key.shaders.fragment];
+            if !include_changed && !changed.iter().any(|name| shaders.contains(&name.as_str())) {
+                continue;
+            }
+
+            match PipelineState::new(
+                desc_layouts,
+                render_pass,
+                key,
+                cache,
+                Rc::clone(&self.device),
+            ) {
+                Ok(reloaded) => {
+                    log::info!("Reloaded pipeline {:?}", key);
+                    self.pipelines.insert(key, reloaded);
+                    self.errors.remove(&key);
+                }
+                Err(e) => {
+                    if self.pipelines.contains_key(&key) {
+                        log::error!("Keeping the old pipeline {:?}: {}", key, e);
+                    } else {
+                        log::error!("Can't build pipeline {:?}: {}", key, e);
+                    }
+                    self.errors.insert(key, e);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, key: &PipelineKey) -> Option<&PipelineState<B>> {
+        self.pipelines.get(key)
+    }
+
+    /// Why the last build of each failed key failed.
+    pub fn get_errors(&self) -> impl Iterator<Item = (&PipelineKey, &PipelineError)> {
+        self.errors.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+        self.errors.clear();
+    }
+}
@@ -0,0 +1,215 @@
+//! Resolves `#include`s and injects `#define`s before GLSL is compiled.
+//!
+//! Only uses `std`, since the build script compiles it in as well.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// Expands GLSL sources for the compiler.
+#[derive(Debug, Clone, Default)]
+pub struct Preprocessor {
+    search_path: Vec<PathBuf>,
+    defines: Vec<(String, String)>,
+}
+
+/// Where a line of preprocessed code came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// A file and a line in it, counting from 1.
+    File(PathBuf, u32),
+    /// A `#define` given to the preprocessor.
+    Define,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::File(path, line) => write!(f, "{}:{}", path.display(), line),
+            Origin::Define => write!(f, "<define>"),
+        }
+    }
+}
+
+/// Preprocessed code, ready to compile.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub code: String,
+    /// The origin of every line of `code`.
+    pub origins: Vec<Origin>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Preprocessor::default()
+    }
+
+    /// Adds a directory to look for included files in, after the directory
+    /// of the including file.
+    pub fn include_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.search_path.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Defines `name` as `value`, right after the `#version` line.
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn process_file<P: AsRef<Path>>(&self, path: P) -> Result<Source, String> {
+        let path = path.as_ref();
+        let code = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.process(path, &code)
+    }
+
+    /// Expands `code`, read from `path`.
+    pub fn process(&self, path: &Path, code: &str) -> Result<Source, String> {
+        let mut source = Source {
+            code: String::new(),
+            origins: Vec::new(),
+        };
+        // Canonical like the includes `resolve` returns, so `#pragma once`
+        // and the cycle check recognize the root file too.
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut included = Vec::new();
+        self.expand(
+            &path,
+            code,
+            &mut source,
+            &mut vec![path.clone()],
+            &mut included,
+        )?;
+
+        // Defines must come after `#version`, which must be first.
+        let at = source
+            .code
+            .lines()
+            .position(|line| line.trim_start().starts_with("#version"))
+            .map_or(0, |i| i + 1);
+        let mut lines: Vec<&str> = source.code.lines().collect();
+        let defines: Vec<String> = self
+            .defines
+            .iter()
+            .map(|(name, value)| format!("#define {} {}", name, value))
+            .collect();
+        lines.splice(at..at, defines.iter().map(String::as_str));
+        let code = lines.join("\n") + "\n";
+        source
+            .origins
+            .splice(at..at, defines.iter().map(|_| Origin::Define));
+
+        Ok(Source {
+            code,
+            origins: source.origins,
+        })
+    }
+
+    fn expand(
+        &self,
+        path: &Path,
+        code: &str,
+        source: &mut Source,
+        stack: &mut Vec<PathBuf>,
+        included: &mut Vec<PathBuf>,
+    ) -> Result<(), String> {
+        for (i, line) in code.lines().enumerate() {
+            let origin = Origin::File(path.to_path_buf(), i as u32 + 1);
+            let directive = line.trim_start();
+
+            if directive.strip_prefix("#pragma").map(str::trim) == Some("once") {
+                if included.iter().any(|p| p == path) {
+                    return Ok(());
+                }
+                included.push(path.to_path_buf());
+                continue;
+            }
+
+            let name = match directive.strip_prefix("#include") {
+                Some(rest) => include_name(rest.trim())
+                    .ok_or_else(|| format!("{}: malformed #include", origin))?,
+                None => {
+                    source.code.push_str(line);
+                    source.code.push('\n');
+                    source.origins.push(origin);
+                    continue;
+                }
+            };
+
+            let file = self
+                .resolve(path, name)
+                .ok_or_else(|| format!("{}: can't find include \"{}\"", origin, name))?;
+            if included.contains(&file) {
+                continue;
+            }
+            if stack.contains(&file) {
+                return Err(format!("{}: \"{}\" includes itself", origin, name));
+            }
+            let code = fs::read_to_string(&file)
+                .map_err(|e| format!("{}: {}: {}", origin, file.display(), e))?;
+
+            stack.push(file.clone());
+            self.expand(&file, &code, source, stack, included)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, from: &Path, name: &str) -> Option<PathBuf> {
+        from.parent()
+            .into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            // So `#pragma once` recognizes files reached through `..`.
+            .map(|path| fs::canonicalize(&path).unwrap_or(path))
+    }
+}
+
+/// The file name in `"name"` or `<name>`.
+fn include_name(rest: &str) -> Option<&str> {
+    let close = match rest.chars().next()? {
+        '"' => '"',
+        '<' => '>',
+        _ => return None,
+    };
+    let end = rest[1..].find(close)?;
+    Some(&rest[1..1 + end])
+}
+
+impl Source {
+    /// Origin of `line` of `code`, counting from 1.
+    pub fn origin(&self, line: u32) -> Option<&Origin> {
+        self.origins.get((line as usize).checked_sub(1)?)
+    }
+
+    /// Rewrites the `<file>:<line>:` locations in a compiler log to point at
+    /// the original sources.
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|line| self.map_log_line(line).unwrap_or_else(|| line.to_string()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// glslang reports e.g. `ERROR: /tmp/x/0.frag:12: 'foo' : undeclared`.
+    fn map_log_line(&self, line: &str) -> Option<String> {
+        let (severity, rest) = line.split_once(": ")?;
+
+        // The file name may contain colons itself, the line number is the
+        // first field made of digits only.
+        let mut start = 0;
+        while let Some(offset) = rest[start..].find(':') {
+            let from = start + offset + 1;
+            let to = from + rest[from..].find(':')?;
+            let number = &rest[from..to];
+            if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) {
+                let origin = self.origin(number.parse().ok()?)?;
+                return Some(format!("{}: {}:{}", severity, origin, &rest[to + 1..]));
+            }
+            start = from;
+        }
+        None
+    }
+}
@@ -0,0 +1,650 @@
+use std::{collections::HashMap, ops::Range};
+
+use hal::{
+    format::{ChannelType, Format},
+    pso,
+};
+
+const MAGIC: u32 = 0x0723_0203;
+
+// Opcodes.
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Decorations.
+const BUFFER_BLOCK: u32 = 3;
+const ARRAY_STRIDE: u32 = 6;
+const MATRIX_STRIDE: u32 = 7;
+const BUILT_IN: u32 = 11;
+const NON_WRITABLE: u32 = 24;
+const LOCATION: u32 = 30;
+const BINDING: u32 = 33;
+const DESCRIPTOR_SET: u32 = 34;
+const OFFSET: u32 = 35;
+
+// Storage classes.
+const UNIFORM_CONSTANT: u32 = 0;
+const INPUT: u32 = 1;
+const UNIFORM: u32 = 2;
+const PUSH_CONSTANT: u32 = 9;
+const STORAGE_BUFFER: u32 = 12;
+
+// Image dimensionality of texel buffers.
+const DIM_BUFFER: u32 = 5;
+
+/// A resource a shader reads through a descriptor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub set: u32,
+    pub binding: pso::DescriptorBinding,
+    pub ty: pso::DescriptorType,
+    pub count: pso::DescriptorArrayIndex,
+    pub stages: pso::ShaderStageFlags,
+    /// Variable name, if the module kept debug names.
+    pub name: Option<String>,
+}
+
+/// The interface of one or more shader stages, read from their SPIR-V.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderReflection {
+    pub stages: pso::ShaderStageFlags,
+    pub bindings: Vec<Binding>,
+    /// Bytes of push constants used, per stage.
+    pub push_constants: Vec<(pso::ShaderStageFlags, Range<u32>)>,
+    /// Vertex shader inputs with the format they are declared with.
+    pub inputs: Vec<(pso::Location, Format)>,
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar {
+        float: bool,
+        signed: bool,
+        width: u32,
+    },
+    Vector {
+        component: u32,
+        count: u32,
+    },
+    Matrix {
+        column: u32,
+        count: u32,
+    },
+    Image {
+        dim: u32,
+        sampled: u32,
+    },
+    Sampler,
+    SampledImage,
+    Array {
+        element: u32,
+        length: u32,
+    },
+    RuntimeArray {
+        element: u32,
+    },
+    Struct {
+        members: Vec<u32>,
+    },
+    Pointer {
+        ty: u32,
+    },
+}
+
+#[derive(Default)]
+struct Module {
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Pointer type, id and storage class.
+    variables: Vec<(u32, u32, u32)>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+    execution_model: Option<u32>,
+}
+
+impl Module {
+    fn parse(spirv: &[u32]) -> Result<Self, String> {
+        if spirv.len() < 5 || spirv[0] != MAGIC {
+            return Err("not a SPIR-V module".into());
+        }
+
+        let mut module = Module::default();
+        let mut words = &spirv[5..];
+        while !words.is_empty() {
+            let count = (words[0] >> 16) as usize;
+            if count == 0 || count > words.len() {
+                return Err("truncated SPIR-V instruction".into());
+            }
+            let (op, args) = (words[0] & 0xffff, &words[1..count]);
+            words = &words[count..];
+
+            let arg = |i: usize| args.get(i).copied().unwrap_or(0);
+            match op {
+                OP_NAME if !args.is_empty() => {
+                    module.names.insert(args[0], string(&args[1..]));
+                }
+                OP_ENTRY_POINT if module.execution_model.is_none() => {
+                    module.execution_model = Some(arg(0));
+                }
+                OP_TYPE_INT => {
+                    module.types.insert(
+                        arg(0),
+                        Type::Scalar {
+                            float: false,
+                            signed: arg(2) != 0,
+                            width: arg(1),
+                        },
+                    );
+                }
+                OP_TYPE_FLOAT => {
+                    module.types.insert(
+                        arg(0),
+                        Type::Scalar {
+                            float: true,
+                            signed: true,
+                            width: arg(1),
+                        },
+                    );
+                }
+                OP_TYPE_VECTOR => {
+                    module.types.insert(
+                        arg(0),
+                        Type::Vector {
+                            component: arg(1),
+                            count: arg(2),
+                        },
+                    );
+                }
+                OP_TYPE_MATRIX => {
+                    module.types.insert(
+                        arg(0),
+                        Type::Matrix {
+                            column: arg(1),
+                            count: arg(2),
+                        },
+                    );
+                }
+                OP_TYPE_IMAGE => {
+                    module.types.insert(
+                        arg(0),
+                        Type::Image {
+                            dim: arg(2),
+                            sampled: arg(6),
+                        },
+                    );
+                }
+                OP_TYPE_SAMPLER => {
+                    module.types.insert(arg(0), Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    module.types.insert(arg(0), Type::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    module.types.insert(
+                        arg(0),
+                        Type::Array {
+                            element: arg(1),
+                            length: arg(2),
+                        },
+                    );
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    module
+                        .types
+                        .insert(arg(0), Type::RuntimeArray { element: arg(1) });
+                }
+                OP_TYPE_STRUCT if !args.is_empty() => {
+                    module.types.insert(
+                        args[0],
+                        Type::Struct {
+                            members: args[1..].to_vec(),
+                        },
+                    );
+                }
+                OP_TYPE_POINTER => {
+                    module.types.insert(arg(0), Type::Pointer { ty: arg(2) });
+                }
+                OP_CONSTANT => {
+                    module.constants.insert(arg(1), arg(2));
+                }
+                OP_VARIABLE => module.variables.push((arg(0), arg(1), arg(2))),
+                OP_DECORATE => {
+                    module.decorations.insert((arg(0), arg(1)), arg(2));
+                }
+                OP_MEMBER_DECORATE => {
+                    module
+                        .member_decorations
+                        .insert((arg(0), arg(1), arg(2)), arg(3));
+                }
+                _ => {}
+            }
+        }
+        Ok(module)
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    fn ty(&self, id: u32) -> Result<&Type, String> {
+        self.types
+            .get(&id)
+            .ok_or_else(|| format!("undefined type %{}", id))
+    }
+
+    fn name(&self, id: u32) -> Option<String> {
+        self.names.get(&id).filter(|name| !name.is_empty()).cloned()
+    }
+
+    /// Size in bytes under the explicit layout of blocks.
+    fn size(&self, id: u32) -> Result<u32, String> {
+        Ok(match self.ty(id)? {
+            Type::Scalar { width, .. } => width / 8,
+            Type::Vector { component, count } => self.size(*component)? * count,
+            Type::Matrix { column, count } => self.size(*column)? * count,
+            Type::Array { element, length } => {
+                let stride = match self.decoration(id, ARRAY_STRIDE) {
+                    Some(stride) => stride,
+                    None => self.size(*element)?,
+                };
+                stride * self.constants.get(length).copied().unwrap_or(1)
+            }
+            Type::RuntimeArray { .. } => 0,
+            Type::Struct { members } => self.member_range(id, members)?.end,
+            _ => 0,
+        })
+    }
+
+    /// Bytes spanned by the members of struct `id`.
+    fn member_range(&self, id: u32, members: &[u32]) -> Result<Range<u32>, String> {
+        let mut range: Option<Range<u32>> = None;
+        for (i, &member) in members.iter().enumerate() {
+            let offset = self
+                .member_decorations
+                .get(&(id, i as u32, OFFSET))
+                .copied()
+                .unwrap_or(0);
+            let size = match (
+                self.ty(member)?,
+                self.member_decorations.get(&(id, i as u32, MATRIX_STRIDE)),
+            ) {
+                (Type::Matrix { count, .. }, Some(stride)) => stride * count,
+                _ => self.size(member)?,
+            };
+            range = Some(match range {
+                Some(range) => range.start.min(offset)..range.end.max(offset + size),
+                None => offset..offset + size,
+            });
+        }
+        Ok(range.unwrap_or(0..0))
+    }
+
+    fn stage(&self) -> pso::ShaderStageFlags {
+        match self.execution_model {
+            Some(0) => pso::ShaderStageFlags::VERTEX,
+            Some(1) => pso::ShaderStageFlags::HULL,
+            Some(2) => pso::ShaderStageFlags::DOMAIN,
+            Some(3) => pso::ShaderStageFlags::GEOMETRY,
+            Some(4) => pso::ShaderStageFlags::FRAGMENT,
+            Some(5) => pso::ShaderStageFlags::COMPUTE,
+            _ => pso::ShaderStageFlags::empty(),
+        }
+    }
+
+    fn descriptor_type(
+        &self,
+        var: u32,
+        ty: u32,
+        class: u32,
+    ) -> Result<Option<pso::DescriptorType>, String> {
+        // readonly buffers mark every member, readonly images the variable.
+        let read_only = match self.ty(ty)? {
+            Type::Struct { members } => (0..members.len() as u32)
+                .all(|i| self.member_decorations.contains_key(&(ty, i, NON_WRITABLE))),
+            _ => self.decoration(var, NON_WRITABLE).is_some(),
+        };
+        Ok(Some(match (class, self.ty(ty)?) {
+            (UNIFORM, _) if self.decoration(ty, BUFFER_BLOCK).is_some() => {
+                pso::DescriptorType::Buffer {
+                    ty: pso::BufferDescriptorType::Storage { read_only },
+                    format: pso::BufferDescriptorFormat::Structured {
+                        dynamic_offset: false,
+                    },
+                }
+            }
+            (UNIFORM, _) => pso::DescriptorType::Buffer {
+                ty: pso::BufferDescriptorType::Uniform,
+                format: pso::BufferDescriptorFormat::Structured {
+                    dynamic_offset: false,
+                },
+            },
+            (STORAGE_BUFFER, _) => pso::DescriptorType::Buffer {
+                ty: pso::BufferDescriptorType::Storage { read_only },
+                format: pso::BufferDescriptorFormat::Structured {
+                    dynamic_offset: false,
+                },
+            },
+            (UNIFORM_CONSTANT, Type::Sampler) => pso::DescriptorType::Sampler,
+            (UNIFORM_CONSTANT, Type::SampledImage) => pso::DescriptorType::Image {
+                ty: pso::ImageDescriptorType::Sampled { with_sampler: true },
+            },
+            (
+                UNIFORM_CONSTANT,
+                Type::Image {
+                    dim: DIM_BUFFER,
+                    sampled,
+                },
+            ) => pso::DescriptorType::Buffer {
+                ty: if *sampled == 2 {
+                    pso::BufferDescriptorType::Storage { read_only }
+                } else {
+                    pso::BufferDescriptorType::Uniform
+                },
+                format: pso::BufferDescriptorFormat::Texel,
+            },
+            (UNIFORM_CONSTANT, Type::Image { sampled: 2, .. }) => pso::DescriptorType::Image {
+                ty: pso::ImageDescriptorType::Storage { read_only },
+            },
+            (UNIFORM_CONSTANT, Type::Image { .. }) => pso::DescriptorType::Image {
+                ty: pso::ImageDescriptorType::Sampled {
+                    with_sampler: false,
+                },
+            },
+            _ => return Ok(None),
+        }))
+    }
+
+    fn input_format(&self, ty: u32) -> Result<Format, String> {
+        let (component, count) = match self.ty(ty)? {
+            Type::Vector { component, count } => (*component, *count),
+            Type::Scalar { .. } => (ty, 1),
+            other => return Err(format!("unsupported vertex input type {:?}", other)),
+        };
+        let (float, signed) = match self.ty(component)? {
+            Type::Scalar { float, signed, .. } => (*float, *signed),
+            other => return Err(format!("unsupported vertex input component {:?}", other)),
+        };
+        const FORMATS: [[Format; 4]; 3] = [
+            [
+                Format::R32Sfloat,
+                Format::Rg32Sfloat,
+                Format::Rgb32Sfloat,
+                Format::Rgba32Sfloat,
+            ],
+            [
+                Format::R32Sint,
+                Format::Rg32Sint,
+                Format::Rgb32Sint,
+                Format::Rgba32Sint,
+            ],
+            [
+                Format::R32Uint,
+                Format::Rg32Uint,
+                Format::Rgb32Uint,
+                Format::Rgba32Uint,
+            ],
+        ];
+        let class = if float {
+            0
+        } else if signed {
+            1
+        } else {
+            2
+        };
+        Ok(FORMATS[class][(count.clamp(1, 4) - 1) as usize])
+    }
+}
+
+/// Decodes a nul terminated string packed into words.
+fn string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .take_while(|&b| b != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl ShaderReflection {
+    /// Reads the interface of the first entry point in `spirv`.
+    ///
+    /// A module without entry points has an empty interface.
+    pub fn new(spirv: &[u32]) -> Result<Self, String> {
+        let module = Module::parse(spirv)?;
+        let stage = module.stage();
+        let mut reflection = ShaderReflection {
+            stages: stage,
+            bindings: Vec::new(),
+            push_constants: Vec::new(),
+            inputs: Vec::new(),
+        };
+
+        for &(pointer, var, class) in &module.variables {
+            let ty = match module.ty(pointer)? {
+                Type::Pointer { ty } => *ty,
+                _ => return Err(format!("variable %{} is not a pointer", var)),
+            };
+
+            match class {
+                INPUT if stage == pso::ShaderStageFlags::VERTEX => {
+                    if let Some(location) = module.decoration(var, LOCATION) {
+                        if module.decoration(var, BUILT_IN).is_none() {
+                            reflection.inputs.push((location, module.input_format(ty)?));
+                        }
+                    }
+                }
+                PUSH_CONSTANT => {
+                    if let Type::Struct { members } = module.ty(ty)? {
+                        let range = module.member_range(ty, members)?;
+                        reflection.push_constants.push((stage, range));
+                    }
+                }
+                UNIFORM_CONSTANT | UNIFORM | STORAGE_BUFFER => {
+                    let (ty, count) = match module.ty(ty)? {
+                        Type::Array { element, length } => (
+                            *element,
+                            module.constants.get(length).copied().unwrap_or(1) as usize,
+                        ),
+                        Type::RuntimeArray { element } => (*element, 0),
+                        _ => (ty, 1),
+                    };
+                    if let Some(desc_ty) = module.descriptor_type(var, ty, class)? {
+                        reflection.bindings.push(Binding {
+                            set: module.decoration(var, DESCRIPTOR_SET).unwrap_or(0),
+                            binding: module.decoration(var, BINDING).unwrap_or(0),
+                            ty: desc_ty,
+                            count,
+                            stages: stage,
+                            name: module.name(var).or_else(|| module.name(ty)),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+        reflection.inputs.sort_by_key(|&(location, _)| location);
+        Ok(reflection)
+    }
+
+    /// Combines the interfaces of stages making up one pipeline.
+    pub fn merge(mut self, other: ShaderReflection) -> Self {
+        self.stages |= other.stages;
+        for binding in other.bindings {
+            match self
+                .bindings
+                .iter_mut()
+                .find(|b| (b.set, b.binding) == (binding.set, binding.binding))
+            {
+                Some(existing) => existing.stages |= binding.stages,
+                None => self.bindings.push(binding),
+            }
+        }
+        self.bindings.sort_by_key(|b| (b.set, b.binding));
+        self.push_constants.extend(other.push_constants);
+        self.inputs.extend(other.inputs);
+        self
+    }
+
+    /// Layout bindings for descriptor set `set`, to create a `DescSetLayout`
+    /// from.
+    pub fn set_bindings(&self, set: u32) -> Vec<pso::DescriptorSetLayoutBinding> {
+        self.bindings
+            .iter()
+            .filter(|b| b.set == set)
+            .map(|b| pso::DescriptorSetLayoutBinding {
+                binding: b.binding,
+                ty: b.ty,
+                count: b.count.max(1),
+                stage_flags: b.stages,
+                immutable_samplers: false,
+            })
+            .collect()
+    }
+
+    /// Checks that `layouts`, one per set, provide every binding used.
+    pub fn check_bindings(
+        &self,
+        layouts: &[&[pso::DescriptorSetLayoutBinding]],
+    ) -> Result<(), String> {
+        for used in &self.bindings {
+            let what = || match &used.name {
+                Some(name) => format!("`{}` (set {}, binding {})", name, used.set, used.binding),
+                None => format!("set {}, binding {}", used.set, used.binding),
+            };
+            let provided = layouts
+                .get(used.set as usize)
+                .and_then(|layout| layout.iter().find(|b| b.binding == used.binding))
+                .ok_or_else(|| format!("{} has no descriptor in the pipeline layout", what()))?;
+
+            if !compatible(&used.ty, &provided.ty) {
+                return Err(format!(
+                    "{} is a {:?} in the shader but a {:?} in the layout",
+                    what(),
+                    used.ty,
+                    provided.ty
+                ));
+            }
+            if provided.count < used.count {
+                return Err(format!(
+                    "{} is an array of {} in the shader but has {} descriptors",
+                    what(),
+                    used.count,
+                    provided.count
+                ));
+            }
+            if !provided.stage_flags.contains(used.stages) {
+                return Err(format!("{} isn't visible to {:?}", what(), used.stages));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `ranges` cover the push constants each stage reads.
+    pub fn check_push_constants(
+        &self,
+        ranges: &[(pso::ShaderStageFlags, Range<u32>)],
+    ) -> Result<(), String> {
+        for (stage, used) in &self.push_constants {
+            let covered = ranges.iter().any(|(stages, range)| {
+                stages.contains(*stage) && range.start <= used.start && used.end <= range.end
+            });
+            if !covered {
+                return Err(format!(
+                    "{:?} reads push constants {:?} outside the pipeline layout",
+                    stage, used
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `attributes` feed every vertex input with the same kind of
+    /// number, float, signed or unsigned.
+    pub fn check_inputs(&self, attributes: &[pso::AttributeDesc]) -> Result<(), String> {
+        for &(location, format) in &self.inputs {
+            let attribute = attributes
+                .iter()
+                .find(|a| a.location == location)
+                .ok_or_else(|| format!("vertex input {} has no attribute", location))?;
+            if number_kind(attribute.element.format) != number_kind(format) {
+                return Err(format!(
+                    "vertex input {} is {:?} in the shader but {:?} in the vertex layout",
+                    location, format, attribute.element.format
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a shader declaring `used` can read a descriptor of type `provided`.
+///
+/// Dynamic offsets don't concern the shader, and a resource the shader only
+/// reads may still be bound writable.
+fn compatible(used: &pso::DescriptorType, provided: &pso::DescriptorType) -> bool {
+    relaxed(*used) == relaxed(*provided) && (read_only(used) || !read_only(provided))
+}
+
+fn relaxed(ty: pso::DescriptorType) -> pso::DescriptorType {
+    use pso::{BufferDescriptorFormat as Bf, BufferDescriptorType as Bt, DescriptorType as Dt};
+
+    match ty {
+        Dt::Buffer { ty, format } => Dt::Buffer {
+            ty: match ty {
+                Bt::Storage { .. } => Bt::Storage { read_only: false },
+                ty => ty,
+            },
+            format: match format {
+                Bf::Structured { .. } => Bf::Structured {
+                    dynamic_offset: false,
+                },
+                format => format,
+            },
+        },
+        Dt::Image {
+            ty: pso::ImageDescriptorType::Storage { .. },
+        } => Dt::Image {
+            ty: pso::ImageDescriptorType::Storage { read_only: false },
+        },
+        ty => ty,
+    }
+}
+
+fn read_only(ty: &pso::DescriptorType) -> bool {
+    match ty {
+        pso::DescriptorType::Buffer {
+            ty: pso::BufferDescriptorType::Storage { read_only },
+            ..
+        }
+        | pso::DescriptorType::Image {
+            ty: pso::ImageDescriptorType::Storage { read_only },
+        } => *read_only,
+        _ => false,
+    }
+}
+
+fn number_kind(format: Format) -> u8 {
+    match format.base_format().1 {
+        ChannelType::Uint => 1,
+        ChannelType::Sint => 2,
+        _ => 0,
+    }
+}
@@ -1,71 +1,190 @@
 use std::{
     cell::RefCell,
-    io::Cursor,
+    collections::HashMap,
+    io::{self, Cursor},
     iter,
+    path::PathBuf,
     rc::Rc,
+    time::Instant,
 };
 
 use hal::{
     buffer, command,
-    image as i, pass, pool,
+    image as i, memory as m, pass, pool,
     prelude::*,
     pso,
     queue::Submission,
-    Backend,
+    window as w,
+    Backend, IndexType,
 };
 
-use cgmath::Vector2;
+use cgmath::{BaseFloat, Matrix4, SquareMatrix, Vector2};
 
+use rgb::RGBA8;
+
+use crate::adapter::AdapterState;
+use crate::batch::{self, Batcher, IndexWidth};
+use crate::cache::PersistentPipelineCache;
+use crate::camera::{Camera2D, ViewController, YAxis};
+use crate::shapes::{Rectangle, Shape, ShapeFormat};
 use crate::swapchain::SwapchainState;
 use crate::device::DeviceState;
 use crate::backend::BackendState;
-use crate::item::{Uniform, ImageState};
-use crate::buffer::{BufferState, FramebufferState};
-use crate::pipeline::{PipelineState, Vertex};
-use crate::desc::DescSetLayout;
-
-pub const QUAD: [Vertex; 6] = [
-    Vertex {
-        a_pos: Vector2::new(-0.5, 0.33),
-        a_uv: Vector2::new(0.0, 1.0),
-    },
-    Vertex {
-        a_pos: Vector2::new(0.5, 0.33),
-        a_uv: Vector2::new(1.0, 1.0),
-    },
-    Vertex {
-        a_pos: Vector2::new(0.5, -0.33),
-        a_uv: Vector2::new(1.0, 0.0),
-    },
-    Vertex {
-        a_pos: Vector2::new(-0.5, 0.33),
-        a_uv: Vector2::new(0.0, 1.0),
-    },
-    Vertex {
-        a_pos: Vector2::new(0.5, -0.33),
-        a_uv: Vector2::new(1.0, 0.0),
-    },
-    Vertex {
-        a_pos: Vector2::new(-0.5, -0.33),
-        a_uv: Vector2::new(0.0, 0.0),
+use crate::item::{DynamicUniform, Uniform, ImageState};
+use crate::offscreen::OffscreenState;
+use crate::buffer::{BufferState, FrameData, FramebufferState, Index, IndexBuffer};
+use crate::shader::{self, ShaderWatcher};
+use crate::pipeline::{
+    BlendMode, DrawUniforms, PipelineCache, PipelineError, PipelineKey, Vertex, PUSH_CONSTANTS_SIZE, QUAD_SHADERS,
+};
+use crate::compute::{ComputePipelineState, ComputeResource, StorageBuffer, StorageImage, SHADER_STAGES};
+use crate::desc::{DescAllocator, DescSet, DescSetLayout, DescSetWrite};
+
+/// Descriptor sets each pool of the renderer's `DescAllocator` holds.
+const SETS_PER_POOL: usize = 64;
+
+/// Descriptor sets the quad shaders read textures and the color uniform from.
+const TEXTURE_SET: u32 = 0;
+const UNIFORM_SET: u32 = 1;
+/// The set holding `DrawUniforms`, bound at a dynamic offset per draw.
+const DRAW_SET: u32 = 2;
+
+/// Options fixed when a `RendererState` is created.
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// How many frames the CPU may record ahead of the GPU. Each one has its
+    /// own command pool, fence and vertex/index streams. Headless renderers
+    /// always use one, since every frame draws into the same offscreen image.
+    pub frames_in_flight: u32,
+    /// Draw into an offscreen image of this size instead of the window.
+    pub headless: Option<w::Extent2D>,
+    /// File the pipeline cache is loaded from and saved to, so pipelines
+    /// don't have to be built from scratch on every start.
+    pub pipeline_cache: Option<PathBuf>,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        RendererConfig {
+            frames_in_flight: 2,
+            headless: None,
+            pipeline_cache: None,
+        }
+    }
+}
+
+/// Where the demo logo is drawn by `draw`, in normalized device coordinates.
+const LOGO_RECT: Rectangle = Rectangle {
+    position: Vector2::new(-0.4, -0.264),
+    wh: Vector2::new(0.8, 0.528),
+    color: RGBA8::new(255, 255, 255, 255),
+    format: ShapeFormat::Fill,
+};
+
+/// Handle to a texture owned by a `RendererState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureId(usize);
+
+/// Handle to static geometry created with `RendererState::create_mesh`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeshId(usize);
+
+/// Indexed geometry kept on the GPU between frames.
+struct Mesh<B: Backend> {
+    vertices: BufferState<B>,
+    indices: IndexBuffer<B>,
+    topology: pso::Primitive,
+    /// A line list tracing the triangles, for wireframes on devices
+    /// without `PolygonMode::Line`.
+    edges: Option<IndexBuffer<B>>,
+}
+
+impl<B: Backend> Mesh<B> {
+    /// The index buffer to draw, in wireframe or not.
+    fn index_buffer(&self, wireframe: bool) -> &IndexBuffer<B> {
+        match &self.edges {
+            Some(edges) if wireframe => edges,
+            _ => &self.indices,
+        }
+    }
+}
+
+fn get_texture<B: Backend>(textures: &[Option<ImageState<B>>], texture: TextureId) -> &ImageState<B> {
+    textures[texture.0].as_ref().expect("texture was destroyed")
+}
+
+fn get_mesh<B: Backend>(meshes: &[Option<Mesh<B>>], mesh: MeshId) -> &Mesh<B> {
+    meshes[mesh.0].as_ref().expect("mesh was destroyed")
+}
+
+/// What batched geometry must share to be drawn together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DrawKey {
+    texture: TextureId,
+    blend: BlendMode,
+    topology: pso::Primitive,
+    wireframe: bool,
+    /// Index of the draw's `DrawUniforms` block this frame.
+    uniforms: u32,
+}
+
+/// A draw recorded between `begin_frame` and `end_frame`, in submission order.
+#[derive(Debug, Clone, Copy)]
+enum DrawCall {
+    Batch(usize),
+    Mesh {
+        mesh: MeshId,
+        blend: BlendMode,
+        wireframe: bool,
+        uniforms: u32,
     },
-];
+}
+
+/// Which vertex and index buffers are currently bound while recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bound {
+    Stream(IndexWidth),
+    /// A mesh, with its edges bound instead of its triangles or not.
+    Mesh(MeshId, bool),
+}
 
 pub struct RendererState<B: Backend> {
-    uniform_desc_pool: Option<B::DescriptorPool>,
-    img_desc_pool: Option<B::DescriptorPool>,
     swapchain: SwapchainState,
+    offscreen: Option<OffscreenState<B>>,
     device: Rc<RefCell<DeviceState<B>>>,
     pub backend: BackendState<B>,
-    vertex_buffer: BufferState<B>,
     render_pass: RenderPassState<B>,
     uniform: Uniform<B>,
-    pipeline: PipelineState<B>,
+    draw_uniform: DynamicUniform<B>,
+    /// What draws queued from now on get in their uniform block.
+    draw_uniforms: DrawUniforms,
+    /// The block holding `draw_uniforms` this frame, once pushed.
+    draw_block: Option<u32>,
     framebuffer: FramebufferState<B>,
     viewport: pso::Viewport,
-    image: ImageState<B>,
+    /// `None` once destroyed.
+    textures: Vec<Option<ImageState<B>>>,
+    texture_bindings: Vec<pso::DescriptorSetLayoutBinding>,
+    /// Declared after every field holding descriptor sets, so its pools are
+    /// destroyed after them.
+    desc_allocator: DescAllocator<B>,
+    white: TextureId,
+    logo: TextureId,
+    /// `None` once destroyed.
+    meshes: Vec<Option<Mesh<B>>>,
+    batcher: Batcher<DrawKey>,
+    blend_mode: BlendMode,
+    wireframe: bool,
+    pipeline_cache: PersistentPipelineCache<B>,
+    shader_watcher: Option<ShaderWatcher>,
+    camera: Option<Camera2D>,
+    view_controller: Option<ViewController>,
+    last_frame: Option<Instant>,
+    draw_calls: Vec<DrawCall>,
     pub recreate_swapchain: bool,
     color: pso::ColorValue,
+    /// `color` changed since it was last written to `uniform`.
+    color_changed: bool,
     bg_color: pso::ColorValue,
     pub cur_color: Color,
     pub cur_value: u32,
@@ -80,127 +199,115 @@ pub enum Color {
 }
 
 impl<B: Backend> RendererState<B> {
-    pub unsafe fn new(mut backend: BackendState<B>) -> Self {
+    /// Creates a renderer presenting to the window of `backend`.
+    pub unsafe fn new(backend: BackendState<B>) -> Self {
+        Self::with_config(backend, RendererConfig::default())
+    }
+
+    /// Creates a renderer drawing into an offscreen image of `extent`, read
+    /// back with `read_pixels`. `backend` doesn't need a window or surface.
+    pub unsafe fn new_headless(backend: BackendState<B>, extent: w::Extent2D) -> Self {
+        Self::with_config(
+            backend,
+            RendererConfig {
+                headless: Some(extent),
+                ..RendererConfig::default()
+            },
+        )
+    }
+
+    pub unsafe fn with_config(mut backend: BackendState<B>, config: RendererConfig) -> Self {
+        assert!(config.frames_in_flight > 0, "Need at least one frame in flight");
+        let frames_in_flight = match config.headless {
+            Some(_) => 1,
+            None => config.frames_in_flight,
+        };
+
         let device = Rc::new(RefCell::new(DeviceState::new(
             backend.adapter.adapter.take().unwrap(),
-            &backend.surface,
+            backend.surface.as_ref(),
         )));
 
-        let image_desc = DescSetLayout::new(
+        let quad = QUAD_SHADERS.reflect().unwrap_or_else(|e| panic!("Can't load the built-in shaders: {}", e));
+        let texture_bindings = quad.set_bindings(TEXTURE_SET);
+        let uniform_desc = DescSetLayout::new(Rc::clone(&device), quad.set_bindings(UNIFORM_SET));
+        let draw_desc = DescSetLayout::new(Rc::clone(&device), with_dynamic_offsets(quad.set_bindings(DRAW_SET)));
+
+        // Room for every set to hold a texture, a uniform and a few storage
+        // resources, whichever the set is for.
+        let mut desc_allocator = DescAllocator::new(
             Rc::clone(&device),
+            SETS_PER_POOL,
             vec![
-                pso::DescriptorSetLayoutBinding {
-                    binding: 0,
+                pso::DescriptorRangeDesc {
                     ty: pso::DescriptorType::Image {
                         ty: pso::ImageDescriptorType::Sampled {
                             with_sampler: false,
                         },
                     },
-                    count: 1,
-                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
-                    immutable_samplers: false,
+                    count: SETS_PER_POOL,
                 },
-                pso::DescriptorSetLayoutBinding {
-                    binding: 1,
+                pso::DescriptorRangeDesc {
                     ty: pso::DescriptorType::Sampler,
-                    count: 1,
-                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
-                    immutable_samplers: false,
+                    count: SETS_PER_POOL,
                 },
-            ],
-        );
-
-        let uniform_desc = DescSetLayout::new(
-            Rc::clone(&device),
-            vec![pso::DescriptorSetLayoutBinding {
-                binding: 0,
-                ty: pso::DescriptorType::Buffer {
-                    ty: pso::BufferDescriptorType::Uniform,
-                    format: pso::BufferDescriptorFormat::Structured {
-                        dynamic_offset: false,
+                pso::DescriptorRangeDesc {
+                    ty: pso::DescriptorType::Buffer {
+                        ty: pso::BufferDescriptorType::Uniform,
+                        format: pso::BufferDescriptorFormat::Structured {
+                            dynamic_offset: false,
+                        },
                     },
+                    count: SETS_PER_POOL,
                 },
-                count: 1,
-                stage_flags: pso::ShaderStageFlags::FRAGMENT,
-                immutable_samplers: false,
-            }],
-        );
-
-        let mut img_desc_pool = device
-            .borrow()
-            .device
-            .create_descriptor_pool(
-                1, // # of sets
-                &[
-                    pso::DescriptorRangeDesc {
-                        ty: pso::DescriptorType::Image {
-                            ty: pso::ImageDescriptorType::Sampled {
-                                with_sampler: false,
-                            },
+                pso::DescriptorRangeDesc {
+                    ty: pso::DescriptorType::Buffer {
+                        ty: pso::BufferDescriptorType::Uniform,
+                        format: pso::BufferDescriptorFormat::Structured {
+                            dynamic_offset: true,
                         },
-                        count: 1,
-                    },
-                    pso::DescriptorRangeDesc {
-                        ty: pso::DescriptorType::Sampler,
-                        count: 1,
                     },
-                ],
-                pso::DescriptorPoolCreateFlags::empty(),
-            )
-            .ok();
-
-        let mut uniform_desc_pool = device
-            .borrow()
-            .device
-            .create_descriptor_pool(
-                1, // # of sets
-                &[pso::DescriptorRangeDesc {
+                    count: SETS_PER_POOL,
+                },
+                pso::DescriptorRangeDesc {
                     ty: pso::DescriptorType::Buffer {
-                        ty: pso::BufferDescriptorType::Uniform,
+                        ty: pso::BufferDescriptorType::Storage { read_only: false },
                         format: pso::BufferDescriptorFormat::Structured {
                             dynamic_offset: false,
                         },
                     },
-                    count: 1,
-                }],
-                pso::DescriptorPoolCreateFlags::empty(),
-            )
-            .ok();
+                    count: SETS_PER_POOL * 4,
+                },
+                pso::DescriptorRangeDesc {
+                    ty: pso::DescriptorType::Image {
+                        ty: pso::ImageDescriptorType::Storage { read_only: false },
+                    },
+                    count: SETS_PER_POOL * 4,
+                },
+            ],
+        );
 
-        let image_desc = image_desc.create_desc_set(img_desc_pool.as_mut().unwrap());
-        let uniform_desc = uniform_desc.create_desc_set(uniform_desc_pool.as_mut().unwrap());
+        let uniform_desc = uniform_desc
+            .create_desc_set(&mut desc_allocator)
+            .expect("Can't allocate descriptor set");
+        let draw_desc = draw_desc
+            .create_desc_set(&mut desc_allocator)
+            .expect("Can't allocate descriptor set");
 
         println!("Memory types: {:?}", backend.adapter.memory_types);
 
-        const IMAGE_LOGO: &'static [u8] = include_bytes!("bin/data/logo.png");
-        let img = image::load(Cursor::new(&IMAGE_LOGO[..]), image::ImageFormat::Png)
+        const IMAGE_LOGO: &[u8] = include_bytes!("bin/data/logo.png");
+        let img = image::load(Cursor::new(IMAGE_LOGO), image::ImageFormat::Png)
             .unwrap()
-            .to_rgba();
-
-        let mut staging_pool = device
-            .borrow()
-            .device
-            .create_command_pool(
-                device.borrow().queues.family,
-                pool::CommandPoolCreateFlags::empty(),
-            )
-            .expect("Can't create staging command pool");
+            .to_rgba8();
 
-        let image = ImageState::new(
-            image_desc,
-            &img,
-            &backend.adapter,
-            buffer::Usage::TRANSFER_SRC,
-            &mut device.borrow_mut(),
-            &mut staging_pool,
-        );
+        // Untextured shapes sample this so they can share the textured pipeline.
+        let white_img = image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255]));
 
-        let vertex_buffer = BufferState::new::<Vertex>(
-            Rc::clone(&device),
-            &QUAD,
-            buffer::Usage::VERTEX,
-            &backend.adapter.memory_types,
-        );
+        let textures = vec![
+            Some(Self::load_texture(&device, &backend.adapter, &mut desc_allocator, &texture_bindings, &white_img)),
+            Some(Self::load_texture(&device, &backend.adapter, &mut desc_allocator, &texture_bindings, &img)),
+        ];
 
         let uniform = Uniform::new(
             Rc::clone(&device),
@@ -209,103 +316,866 @@ impl<B: Backend> RendererState<B> {
             uniform_desc,
             0,
         );
-
-        image.wait_for_transfer_completion();
-
-        device.borrow().device.destroy_command_pool(staging_pool);
-
-        let swapchain = SwapchainState::new(&mut *backend.surface, &*device.borrow());
-        let render_pass = RenderPassState::new(&swapchain, Rc::clone(&device));
-        let framebuffer = FramebufferState::new(Rc::clone(&device), swapchain.frame_queue_size);
-
-        let pipeline = PipelineState::new(
-            vec![image.get_layout(), uniform.get_layout()],
-            render_pass.render_pass.as_ref().unwrap(),
+        let draw_uniform = DynamicUniform::new(
             Rc::clone(&device),
+            &backend.adapter.limits,
+            std::mem::size_of::<DrawUniforms>() as u64,
+            frames_in_flight as usize,
+            draw_desc,
+            0,
         );
 
+        let (swapchain, offscreen) = match config.headless {
+            None => {
+                let surface = backend.surface.as_mut().expect("Windowed renderer needs a surface");
+                (SwapchainState::new(surface, &*device.borrow()), None)
+            }
+            Some(extent) => {
+                let swapchain = SwapchainState::headless(extent, OffscreenState::<B>::FORMAT);
+                let offscreen = OffscreenState::new(Rc::clone(&device), &backend.adapter, swapchain.extent);
+                (swapchain, Some(offscreen))
+            }
+        };
+        let pipeline_cache =
+            PersistentPipelineCache::new(Rc::clone(&device), &backend.adapter.info, config.pipeline_cache);
+        let render_pass = RenderPassState::new(&swapchain, offscreen.is_some(), Rc::clone(&device));
+        let framebuffer = FramebufferState::new(Rc::clone(&device), frames_in_flight);
+
         let viewport = swapchain.make_viewport();
 
         RendererState {
             backend,
             device,
-            image,
-            img_desc_pool,
-            uniform_desc_pool,
-            vertex_buffer,
+            textures,
+            texture_bindings,
+            white: TextureId(0),
+            logo: TextureId(1),
+            meshes: Vec::new(),
+            batcher: Batcher::new(),
+            blend_mode: BlendMode::default(),
+            wireframe: false,
+            pipeline_cache,
+            shader_watcher: None,
+            camera: None,
+            view_controller: None,
+            last_frame: None,
+            draw_calls: Vec::new(),
+            desc_allocator,
             uniform,
+            draw_uniform,
+            draw_uniforms: DrawUniforms::default(),
+            draw_block: None,
             render_pass,
-            pipeline,
             swapchain,
+            offscreen,
             framebuffer,
             viewport,
             recreate_swapchain: false,
             color: [1.0, 1.0, 1.0, 1.0],
+            color_changed: false,
             bg_color: [0.8, 0.8, 0.8, 1.0],
             cur_color: Color::Red,
             cur_value: 0,
         }
     }
 
+    /// Uploads `img` and blocks until the copy has finished.
+    unsafe fn load_texture(
+        device: &Rc<RefCell<DeviceState<B>>>,
+        adapter: &AdapterState<B>,
+        desc_allocator: &mut DescAllocator<B>,
+        bindings: &[pso::DescriptorSetLayoutBinding],
+        img: &image::RgbaImage,
+    ) -> ImageState<B> {
+        let image_desc = DescSetLayout::new(Rc::clone(device), bindings.to_vec());
+        let image_desc = image_desc
+            .create_desc_set(desc_allocator)
+            .expect("Can't allocate descriptor set");
+
+        let mut staging_pool = device
+            .borrow()
+            .device
+            .create_command_pool(
+                device.borrow().queues.family,
+                pool::CommandPoolCreateFlags::empty(),
+            )
+            .expect("Can't create staging command pool");
+
+        let image = ImageState::new(
+            image_desc,
+            img,
+            adapter,
+            buffer::Usage::TRANSFER_SRC,
+            &mut device.borrow_mut(),
+            &mut staging_pool,
+        );
+
+        image.wait_for_transfer_completion();
+
+        device.borrow().device.destroy_command_pool(staging_pool);
+
+        image
+    }
+
+    /// Uploads an image for use with `draw_image`.
+    pub fn create_texture(&mut self, img: &image::RgbaImage) -> TextureId {
+        let image = unsafe {
+            Self::load_texture(
+                &self.device,
+                &self.backend.adapter,
+                &mut self.desc_allocator,
+                &self.texture_bindings,
+                img,
+            )
+        };
+        self.textures.push(Some(image));
+        TextureId(self.textures.len() - 1)
+    }
+
+    /// Frees a texture from `create_texture` and returns its descriptor set
+    /// to the pools. Its id must not be drawn again, nor be queued in the
+    /// frame being recorded.
+    pub fn destroy_texture(&mut self, texture: TextureId) {
+        assert!(
+            texture != self.white && texture != self.logo,
+            "Can't destroy the renderer's own textures"
+        );
+        let mut image = self.textures[texture.0].take().expect("texture already destroyed");
+        // Frames in flight may still sample it.
+        self.device.borrow().device.wait_idle().unwrap();
+        unsafe { self.desc_allocator.free(&mut image.desc) };
+    }
+
+    /// Descriptor pools allocated so far. Stays put while sets are freed as
+    /// fast as they are allocated.
+    pub fn get_desc_pool_count(&self) -> usize {
+        self.desc_allocator.pool_count()
+    }
+
     pub fn recreate_swapchain(&mut self) {
         self.device.borrow().device.wait_idle().unwrap();
 
-        self.swapchain =
-            unsafe { SwapchainState::new(&mut *self.backend.surface, &*self.device.borrow()) };
+        // An offscreen target keeps its size, only the surface can change.
+        if let Some(surface) = self.backend.surface.as_mut() {
+            self.swapchain = unsafe { SwapchainState::new(surface, &*self.device.borrow()) };
+        }
+
+        self.render_pass = unsafe {
+            RenderPassState::new(&self.swapchain, self.offscreen.is_some(), Rc::clone(&self.device))
+        };
+
+        self.viewport = self.swapchain.make_viewport();
+
+        if let Some(camera) = self.camera.as_mut() {
+            camera.set_viewport(self.swapchain.extent.width, self.swapchain.extent.height);
+        }
+    }
+
+    /// Sets the camera everything is drawn through. Without one, positions
+    /// are taken as normalized device coordinates.
+    ///
+    /// The camera's viewport is kept at the size of the render target.
+    pub fn set_camera(&mut self, camera: Option<Camera2D>) {
+        self.camera = camera.map(|mut camera| {
+            camera.set_viewport(self.swapchain.extent.width, self.swapchain.extent.height);
+            camera
+        });
+    }
+
+    pub fn get_camera(&self) -> Option<&Camera2D> {
+        self.camera.as_ref()
+    }
+
+    pub fn get_camera_mut(&mut self) -> Option<&mut Camera2D> {
+        self.camera.as_mut()
+    }
+
+    /// Lets `controller` pan and zoom the camera from the events passed to
+    /// `view_event`. Sets up a pixel space, y down camera if there is none.
+    pub fn set_view_controller(&mut self, controller: Option<ViewController>) {
+        if controller.is_some() && self.camera.is_none() {
+            let extent = self.swapchain.extent;
+            self.camera = Some(Camera2D::new(extent.width, extent.height, YAxis::Down));
+        }
+        self.view_controller = controller;
+    }
+
+    pub fn get_view_controller_mut(&mut self) -> Option<&mut ViewController> {
+        self.view_controller.as_mut()
+    }
+
+    /// Forwards a window event to the view controller. Returns whether it
+    /// was used.
+    pub fn view_event(&mut self, event: &winit::event::WindowEvent) -> bool {
+        match (self.view_controller.as_mut(), self.camera.as_ref()) {
+            (Some(controller), Some(camera)) => controller.handle_event(camera, event),
+            _ => false,
+        }
+    }
+
+    /// Maps a window position in pixels to the space shapes are drawn in,
+    /// e.g. to test the cursor with `Shape::contains`.
+    pub fn screen_to_world(&self, screen: Vector2<f32>) -> Vector2<f32> {
+        match &self.camera {
+            Some(camera) => camera.screen_to_world(screen),
+            None => {
+                let extent = self.swapchain.extent;
+                Vector2::new(
+                    screen.x / extent.width as f32 * 2.0 - 1.0,
+                    screen.y / extent.height as f32 * 2.0 - 1.0,
+                )
+            }
+        }
+    }
+
+    pub fn world_to_screen(&self, world: Vector2<f32>) -> Vector2<f32> {
+        match &self.camera {
+            Some(camera) => camera.world_to_screen(world),
+            None => {
+                let extent = self.swapchain.extent;
+                Vector2::new(
+                    (world.x + 1.0) / 2.0 * extent.width as f32,
+                    (world.y + 1.0) / 2.0 * extent.height as f32,
+                )
+            }
+        }
+    }
+
+    /// Draws the demo logo as a complete frame.
+    pub fn draw(&mut self) {
+        self.begin_frame();
+        self.draw_image(self.logo, LOGO_RECT);
+        self.end_frame();
+    }
+
+    /// Starts recording a new frame, discarding anything not yet submitted.
+    pub fn begin_frame(&mut self) {
+        self.batcher.clear();
+        self.draw_calls.clear();
+        self.draw_uniform.clear();
+        self.draw_block = None;
+        self.reload_shaders();
+
+        let now = Instant::now();
+        let dt = self.last_frame.map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_frame = Some(now);
+        if let (Some(controller), Some(camera)) = (self.view_controller.as_mut(), self.camera.as_mut()) {
+            controller.update(camera, dt);
+        }
+    }
 
-        self.render_pass =
-            unsafe { RenderPassState::new(&self.swapchain, Rc::clone(&self.device)) };
+    /// Queues `shape` in its own color. Positions are in the camera's world
+    /// space, or without a camera in normalized device coordinates with y
+    /// pointing down.
+    ///
+    /// Consecutive shapes and images using the same texture end up in a
+    /// single draw call.
+    ///
+    /// `ShapeFormat::Hairline` shapes are drawn as lines and batch only with
+    /// other line lists.
+    pub fn draw_shape<S: BaseFloat>(&mut self, shape: &impl Shape<S>) {
+        let color = shape.get_color();
+        let topology = shape.topology();
+        let (positions, indices) = shape.vertexes();
 
-        self.framebuffer = unsafe {
-            FramebufferState::new(Rc::clone(&self.device), self.swapchain.frame_queue_size)
+        let vertices: Vec<Vertex> = positions
+            .into_iter()
+            .map(|a_pos| Vertex {
+                a_pos,
+                a_uv: Vector2::new(0.0, 0.0),
+                a_color: color,
+            })
+            .collect();
+
+        self.push_batched(self.white, topology, &vertices, indices.as_deref());
+    }
+
+    /// Queues `vertices` assembled as `topology`, e.g. a `LineStrip` plot or
+    /// a `PointList` scatter, without texture. Lines and points are 1 pixel
+    /// wide.
+    ///
+    /// Lists batch like shapes do, every strip is a draw call of its own.
+    pub fn draw_primitives(&mut self, topology: pso::Primitive, vertices: &[Vertex], indices: Option<&[u16]>) {
+        assert!(
+            !matches!(topology, pso::Primitive::PatchList(_)),
+            "Patch lists need tessellation shaders"
+        );
+        self.push_batched(self.white, topology, vertices, indices);
+    }
+
+    /// Queues `texture` stretched over `rect`, tinted by the rectangle's color.
+    pub fn draw_image(&mut self, texture: TextureId, rect: Rectangle) {
+        let (positions, indices) = rect.format(ShapeFormat::Fill).vertexes();
+
+        let vertices: Vec<Vertex> = positions
+            .into_iter()
+            .map(|a_pos| Vertex {
+                a_pos,
+                a_uv: Vector2::new(
+                    (a_pos.x - rect.position.x) / rect.wh.x,
+                    (a_pos.y - rect.position.y) / rect.wh.y,
+                ),
+                a_color: rect.color,
+            })
+            .collect();
+
+        self.push_batched(texture, pso::Primitive::TriangleList, &vertices, indices.as_deref());
+    }
+
+    fn push_batched(
+        &mut self,
+        texture: TextureId,
+        topology: pso::Primitive,
+        vertices: &[Vertex],
+        indices: Option<&[u16]>,
+    ) {
+        let mut key = DrawKey {
+            texture,
+            blend: self.blend_mode,
+            topology,
+            wireframe: false,
+            uniforms: self.current_draw_block(),
         };
+        // In u32, since unindexed geometry may have more vertices than u16 reaches.
+        let mut edges: Option<Vec<u32>> = None;
+        if self.wireframe && batch::is_triangles(topology) {
+            if self.native_wireframe() {
+                key.wireframe = true;
+            } else {
+                let triangles: Vec<u32> = match indices {
+                    Some(indices) => indices.iter().map(|&i| u32::from(i)).collect(),
+                    None => (0..vertices.len() as u32).collect(),
+                };
+                edges = Some(batch::triangle_edges(topology, &triangles));
+                key.topology = pso::Primitive::LineList;
+            }
+        }
+
+        // Appending to a strip would connect it to the previous one.
+        let strip = matches!(key.topology, pso::Primitive::LineStrip | pso::Primitive::TriangleStrip);
+        if strip {
+            self.batcher.split();
+        }
+        let pushed = match &edges {
+            Some(edges) => self.batcher.push_u32(key, vertices, edges),
+            None => self.batcher.push(key, vertices, indices),
+        };
+        if strip {
+            self.batcher.split();
+        }
+
+        // Nothing was added, so the last batch must not be queued again.
+        if !pushed {
+            return;
+        }
+        let batches = self.batcher.batches().len();
+        match self.draw_calls.last() {
+            Some(DrawCall::Batch(i)) if *i == batches - 1 => (),
+            _ => self.draw_calls.push(DrawCall::Batch(batches - 1)),
+        }
+    }
+
+    /// Uploads the output of `shape.vertexes()` as-is into its own vertex and
+    /// index buffers, to be drawn with `draw_mesh` on any later frame.
+    /// Unindexed shapes too long for `u16` indices get `u32` ones.
+    pub fn create_mesh<S: BaseFloat>(&mut self, shape: &impl Shape<S>) -> MeshId {
+        let color = shape.get_color();
+        let topology = shape.topology();
+        let (positions, indices) = shape.vertexes();
+        let count = positions.len();
+
+        let vertices: Vec<Vertex> = positions
+            .into_iter()
+            .map(|a_pos| Vertex {
+                a_pos,
+                a_uv: Vector2::new(0.0, 0.0),
+                a_color: color,
+            })
+            .collect();
 
-        self.pipeline = unsafe {
-            PipelineState::new(
-                vec![self.image.get_layout(), self.uniform.get_layout()],
+        let mesh = unsafe {
+            let (indices, edges) = match indices {
+                Some(indices) => self.mesh_indices(topology, &indices),
+                None if count <= batch::MAX_U16_VERTICES as usize + 1 => {
+                    self.mesh_indices(topology, &(0..count).map(|i| i as u16).collect::<Vec<_>>())
+                }
+                None => self.mesh_indices(topology, &(0..count as u32).collect::<Vec<_>>()),
+            };
+            Mesh {
+                vertices: BufferState::new(
+                    Rc::clone(&self.device),
+                    &vertices,
+                    buffer::Usage::VERTEX,
+                    &self.backend.adapter.memory_types,
+                ),
+                indices,
+                topology,
+                edges,
+            }
+        };
+        self.meshes.push(Some(mesh));
+        MeshId(self.meshes.len() - 1)
+    }
+
+    /// A mesh's index buffer, plus the edges traced for wireframes when the
+    /// device can't draw them natively.
+    unsafe fn mesh_indices<I: Index>(
+        &self,
+        topology: pso::Primitive,
+        indices: &[I],
+    ) -> (IndexBuffer<B>, Option<IndexBuffer<B>>) {
+        let memory_types = &self.backend.adapter.memory_types;
+        let edges = if batch::is_triangles(topology) && !self.native_wireframe() {
+            let edges = batch::triangle_edges(topology, indices);
+            Some(IndexBuffer::new(Rc::clone(&self.device), &edges, memory_types))
+        } else {
+            None
+        };
+        (IndexBuffer::new(Rc::clone(&self.device), indices, memory_types), edges)
+    }
+
+    /// Frees a mesh's buffers. Its id must not be drawn again, nor be queued
+    /// in the frame being recorded.
+    pub fn destroy_mesh(&mut self, mesh: MeshId) {
+        let mesh = self.meshes[mesh.0].take().expect("mesh already destroyed");
+        // Frames in flight may still read the buffers.
+        self.device.borrow().device.wait_idle().unwrap();
+        drop(mesh);
+    }
+
+    /// Queues a mesh created by `create_mesh`.
+    pub fn draw_mesh(&mut self, mesh: MeshId) {
+        self.batcher.split();
+        let uniforms = self.current_draw_block();
+        self.draw_calls.push(DrawCall::Mesh {
+            mesh,
+            blend: self.blend_mode,
+            wireframe: self.wireframe,
+            uniforms,
+        });
+    }
+
+    /// Sets the transform, tint and time of everything queued from now on,
+    /// until changed again. Each change writes a block into a dynamic uniform
+    /// buffer rather than allocating a descriptor set.
+    pub fn set_draw_uniforms(&mut self, uniforms: DrawUniforms) {
+        if uniforms != self.draw_uniforms {
+            self.draw_uniforms = uniforms;
+            self.draw_block = None;
+        }
+    }
+
+    pub fn get_draw_uniforms(&self) -> DrawUniforms {
+        self.draw_uniforms
+    }
+
+    /// Index of the block holding `draw_uniforms`, pushed on first use.
+    fn current_draw_block(&mut self) -> u32 {
+        match self.draw_block {
+            Some(block) => block,
+            None => {
+                let block = self.draw_uniform.push(&self.draw_uniforms);
+                self.draw_block = Some(block);
+                block
+            }
+        }
+    }
+
+    /// Rebuilds pipelines whose shader sources are edited, at the start of
+    /// the next frame. Shaders that fail to compile are logged and the old
+    /// pipeline is kept.
+    #[cfg(feature = "dev-shaders")]
+    pub fn watch_shaders(&mut self, enabled: bool) {
+        self.shader_watcher = if enabled {
+            Some(ShaderWatcher::new(shader::SOURCE_DIR))
+        } else {
+            None
+        };
+    }
+
+    fn reload_shaders(&mut self) {
+        let changed = match self.shader_watcher.as_mut() {
+            Some(watcher) => watcher.poll(),
+            None => return,
+        };
+        if changed.is_empty() {
+            return;
+        }
+
+        // Frames in flight may still use the pipelines being replaced.
+        self.device.borrow().device.wait_idle().unwrap();
+        unsafe {
+            self.render_pass.pipelines.reload(
+                &changed,
+                &[
+                    get_texture(&self.textures, self.white).get_desc_layout(),
+                    self.uniform.get_desc_layout(),
+                    self.draw_uniform.get_desc_layout(),
+                ],
                 self.render_pass.render_pass.as_ref().unwrap(),
+                Some(self.pipeline_cache.get_cache()),
+            );
+        }
+    }
+
+    /// Pipelines that failed to build, with why. Their draws are skipped
+    /// until a shader reload fixes them.
+    pub fn get_pipeline_errors(&self) -> impl Iterator<Item = (&PipelineKey, &PipelineError)> {
+        self.render_pass.pipelines.get_errors()
+    }
+
+    /// Builds a compute pipeline for shader file `name`, e.g. `"blur.comp"`.
+    pub fn create_compute_pipeline(&self, name: &str) -> Result<ComputePipelineState<B>, PipelineError> {
+        unsafe {
+            ComputePipelineState::new(Rc::clone(&self.device), name, Some(self.pipeline_cache.get_cache()))
+        }
+    }
+
+    /// Builds a compute pipeline from SPIR-V compiled elsewhere, e.g. by
+    /// `shader::compile_file` from a directory of your own shaders.
+    pub fn create_compute_pipeline_from_spirv(
+        &self,
+        spirv: &[u32],
+    ) -> Result<ComputePipelineState<B>, PipelineError> {
+        unsafe {
+            ComputePipelineState::from_spirv(
                 Rc::clone(&self.device),
+                spirv,
+                pso::Specialization::default(),
+                Some(self.pipeline_cache.get_cache()),
             )
-        };
+        }
+    }
 
-        self.viewport = self.swapchain.make_viewport();
+    /// Builds a compute pipeline for a variant of shader file `name`, see
+    /// `shader::try_load_variant`.
+    pub fn create_compute_variant(
+        &self,
+        name: &str,
+        defines: &[(&str, &str)],
+    ) -> Result<ComputePipelineState<B>, PipelineError> {
+        unsafe {
+            ComputePipelineState::new_variant(
+                Rc::clone(&self.device),
+                name,
+                defines,
+                Some(self.pipeline_cache.get_cache()),
+            )
+        }
     }
 
-    pub fn draw(&mut self) {
+    pub fn create_storage_buffer(&self, size: u64) -> StorageBuffer<B> {
+        unsafe { StorageBuffer::new(Rc::clone(&self.device), &self.backend.adapter, size) }
+    }
+
+    /// Creates a `width` by `height` storage image, filled with `contents` if
+    /// given.
+    pub fn create_storage_image(
+        &self,
+        width: u32,
+        height: u32,
+        contents: Option<&image::RgbaImage>,
+    ) -> StorageImage<B> {
+        unsafe { StorageImage::new(Rc::clone(&self.device), &self.backend.adapter, width, height, contents) }
+    }
+
+    /// Allocates descriptor set `set` of `pipeline` and binds `resources` to
+    /// it, by binding number.
+    ///
+    /// Fails when a binding doesn't exist in the shader or expects another
+    /// kind of resource, or when the set has a binding the descriptor pools
+    /// don't hold, like a combined image sampler. Free the set with
+    /// `free_compute_set` once done: a set that is just dropped stays
+    /// allocated, and takes up room in its pool, until the renderer is
+    /// dropped.
+    pub fn create_compute_set(
+        &mut self,
+        pipeline: &ComputePipelineState<B>,
+        set: u32,
+        resources: &[(pso::DescriptorBinding, ComputeResource<B>)],
+    ) -> Result<DescSet<B>, PipelineError> {
+        let layout = pipeline
+            .get_set_layout(set)
+            .ok_or_else(|| PipelineError::Interface(format!("the shader has no descriptor set {}", set)))?;
+        let bindings = layout.get_bindings();
+        // Allocating a set the pools have no room for at all would only add
+        // a pool that can't hold it either.
+        if let Some(unsupported) = bindings.iter().find(|b| !self.desc_allocator.supports(&b.ty)) {
+            return Err(PipelineError::Interface(format!(
+                "binding {} in set {} is a {:?}, which compute sets can't hold",
+                unsupported.binding, set, unsupported.ty
+            )));
+        }
+        for (binding, resource) in resources {
+            let expected = bindings.iter().find(|b| b.binding == *binding).ok_or_else(|| {
+                PipelineError::Interface(format!("the shader has no binding {} in set {}", binding, set))
+            })?;
+            if !resource.fits(&expected.ty) {
+                return Err(PipelineError::Interface(format!(
+                    "binding {} in set {} expects a {:?}",
+                    binding, set, expected.ty
+                )));
+            }
+        }
+
+        unsafe {
+            let mut desc =
+                DescSet::new(Rc::clone(layout), &mut self.desc_allocator).map_err(PipelineError::Allocation)?;
+            desc.write_to_state(
+                resources
+                    .iter()
+                    .map(|(binding, resource)| DescSetWrite {
+                        binding: *binding,
+                        array_offset: 0,
+                        descriptors: Some(resource.descriptor()),
+                    })
+                    .collect(),
+                &mut self.device.borrow_mut().device,
+            );
+            Ok(desc)
+        }
+    }
+
+    /// Returns a set from `create_compute_set` to the descriptor pools.
+    ///
+    /// `dispatch` waits for the GPU, so any set not bound since is unused.
+    pub fn free_compute_set(&mut self, mut set: DescSet<B>) {
+        unsafe { self.desc_allocator.free(&mut set) }
+    }
+
+    /// Runs `pipeline` over `groups` work groups with `sets` bound from set 0
+    /// and blocks until it is done.
+    ///
+    /// Barriers order the dispatch after all graphics work submitted so far
+    /// and make its writes visible to vertex input, shaders, transfers and
+    /// the host afterwards.
+    pub fn dispatch(
+        &mut self,
+        pipeline: &ComputePipelineState<B>,
+        sets: &[&DescSet<B>],
+        push_constants: &[u32],
+        groups: [u32; 3],
+    ) {
+        let layout = pipeline.pipeline_layout.as_ref().unwrap();
+        let before = pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT | pso::PipelineStage::TRANSFER | SHADER_STAGES;
+        let after = pso::PipelineStage::TRANSFER | pso::PipelineStage::HOST | SHADER_STAGES;
+
+        unsafe {
+            self.device.borrow_mut().submit_once(|cmd_buffer| {
+                cmd_buffer.pipeline_barrier(
+                    before..pso::PipelineStage::COMPUTE_SHADER,
+                    m::Dependencies::empty(),
+                    &[
+                        m::Barrier::AllBuffers(
+                            buffer::Access::SHADER_WRITE | buffer::Access::TRANSFER_WRITE
+                                ..buffer::Access::SHADER_READ | buffer::Access::SHADER_WRITE,
+                        ),
+                        m::Barrier::AllImages(
+                            i::Access::COLOR_ATTACHMENT_WRITE | i::Access::SHADER_WRITE | i::Access::TRANSFER_WRITE
+                                ..i::Access::SHADER_READ | i::Access::SHADER_WRITE,
+                        ),
+                    ],
+                );
+
+                cmd_buffer.bind_compute_pipeline(pipeline.pipeline.as_ref().unwrap());
+                if !sets.is_empty() {
+                    cmd_buffer.bind_compute_descriptor_sets(
+                        layout,
+                        0,
+                        sets.iter().map(|set| set.set.as_ref().unwrap()),
+                        &[],
+                    );
+                }
+                if !push_constants.is_empty() {
+                    cmd_buffer.push_compute_constants(layout, 0, push_constants);
+                }
+                cmd_buffer.dispatch(groups);
+
+                cmd_buffer.pipeline_barrier(
+                    pso::PipelineStage::COMPUTE_SHADER..after,
+                    m::Dependencies::empty(),
+                    &[
+                        m::Barrier::AllBuffers(
+                            buffer::Access::SHADER_WRITE
+                                ..buffer::Access::VERTEX_BUFFER_READ
+                                    | buffer::Access::INDEX_BUFFER_READ
+                                    | buffer::Access::SHADER_READ
+                                    | buffer::Access::TRANSFER_READ
+                                    | buffer::Access::HOST_READ,
+                        ),
+                        m::Barrier::AllImages(
+                            i::Access::SHADER_WRITE..i::Access::SHADER_READ | i::Access::TRANSFER_READ,
+                        ),
+                    ],
+                );
+            });
+        }
+    }
+
+    /// Writes the pipeline cache to the file given in `RendererConfig`.
+    /// Happens on drop as well.
+    pub fn save_pipeline_cache(&self) -> io::Result<()> {
+        self.pipeline_cache.save()
+    }
+
+    /// Sets how everything queued from now on is blended, until changed again.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn get_blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Draws the triangles of everything queued from now on as their edges,
+    /// to inspect how shapes are tessellated.
+    ///
+    /// Uses `PolygonMode::Line` where the device supports it, otherwise the
+    /// triangles are turned into line lists.
+    pub fn set_wireframe(&mut self, enabled: bool) {
+        self.wireframe = enabled;
+    }
+
+    pub fn get_wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    fn native_wireframe(&self) -> bool {
+        self.device
+            .borrow()
+            .features
+            .contains(hal::Features::NON_FILL_POLYGON_MODE)
+    }
+
+    /// Uploads everything queued since `begin_frame` and submits it, then
+    /// presents it if there is a window. `read_pixels` waits for headless frames.
+    pub fn end_frame(&mut self) {
         if self.recreate_swapchain {
             self.recreate_swapchain();
             self.recreate_swapchain = false;
         }
 
-        let surface_image = unsafe {
-            match self.backend.surface.acquire_image(!0) {
-                Ok((image, _)) => image,
-                Err(_) => {
-                    self.recreate_swapchain = true;
-                    return;
-                }
+        // Build any pipeline variants this frame needs before recording.
+        let keys: Vec<(TextureId, PipelineKey)> = self
+            .draw_calls
+            .iter()
+            .map(|draw_call| {
+                let (texture, blend, topology, wireframe) = match *draw_call {
+                    DrawCall::Batch(i) => {
+                        let key = self.batcher.batches()[i].key;
+                        (key.texture, key.blend, key.topology, key.wireframe)
+                    }
+                    DrawCall::Mesh { mesh, blend, wireframe, .. } => {
+                        let mesh = get_mesh(&self.meshes, mesh);
+                        if wireframe && mesh.edges.is_some() {
+                            (self.white, blend, pso::Primitive::LineList, false)
+                        } else {
+                            let wireframe = wireframe && batch::is_triangles(mesh.topology);
+                            (self.white, blend, mesh.topology, wireframe)
+                        }
+                    }
+                };
+                (
+                    texture,
+                    PipelineKey {
+                        blend,
+                        topology,
+                        wireframe,
+                        ..PipelineKey::default()
+                    },
+                )
+            })
+            .collect();
+        for (_, key) in &keys {
+            // Failures are logged and kept for `get_pipeline_errors`, their
+            // draws are skipped below.
+            unsafe {
+                let _ = self.render_pass.pipelines.prepare(
+                    *key,
+                    &[
+                        get_texture(&self.textures, self.white).get_desc_layout(),
+                        self.uniform.get_desc_layout(),
+                        self.draw_uniform.get_desc_layout(),
+                    ],
+                    self.render_pass.render_pass.as_ref().unwrap(),
+                    Some(self.pipeline_cache.get_cache()),
+                );
             }
+        }
+
+        let surface_image = match self.backend.surface.as_mut() {
+            Some(surface) => unsafe {
+                match surface.acquire_image(!0) {
+                    Ok((image, _)) => Some(image),
+                    Err(_) => {
+                        self.recreate_swapchain = true;
+                        return;
+                    }
+                }
+            },
+            None => None,
         };
 
-        let framebuffer = unsafe {
-            self.device
-                .borrow()
-                .device
-                .create_framebuffer(
-                    self.render_pass.render_pass.as_ref().unwrap(),
-                    iter::once(std::borrow::Borrow::borrow(&surface_image)),
-                    self.swapchain.extent,
-                )
-                .unwrap()
+        let surface_framebuffer = unsafe {
+            match &surface_image {
+                Some(image) => Some(
+                    self.device
+                        .borrow()
+                        .device
+                        .create_framebuffer(
+                            self.render_pass.render_pass.as_ref().unwrap(),
+                            iter::once(std::borrow::Borrow::borrow(image)),
+                            self.swapchain.extent,
+                        )
+                        .unwrap(),
+                ),
+                None => {
+                    let view = self.offscreen.as_ref().unwrap().get_view();
+                    self.render_pass.cache_offscreen_framebuffer(view, self.swapchain.extent);
+                    None
+                }
+            }
+        };
+        let framebuffer = match &surface_framebuffer {
+            Some(framebuffer) => framebuffer,
+            None => self.render_pass.get_offscreen_framebuffer(),
         };
 
-        let frame_idx = (self.swapchain.frame_index % self.swapchain.frame_queue_size) as usize;
+        let frame_idx = self.swapchain.frame_index as usize % self.framebuffer.frames_in_flight();
         self.swapchain.frame_index += 1;
 
-        let (command_pool, command_buffers, sem_image_present) =
-            self.framebuffer.get_frame_data(frame_idx);
+        // The slot's command buffers and streams are about to be rewritten.
+        unsafe { self.framebuffer.wait_for_frame(frame_idx) };
+
+        // Every frame in flight reads the one color uniform. It changes
+        // rarely, so wait for all of them rather than keep a copy per frame.
+        if self.color_changed {
+            self.device.borrow().device.wait_idle().unwrap();
+            self.uniform
+                .buffer
+                .as_mut()
+                .unwrap()
+                .update_data(0, &self.color);
+            self.color_changed = false;
+        }
+
+        let FrameData {
+            command_pool,
+            command_buffers,
+            present_semaphore: sem_image_present,
+            fence,
+            vertex_stream,
+            index_stream_u16,
+            index_stream_u32,
+        } = self.framebuffer.get_frame_data(frame_idx);
 
         unsafe {
+            let memory_types = &self.backend.adapter.memory_types;
+            vertex_stream.upload(&self.device, memory_types, self.batcher.vertices());
+            index_stream_u16.upload(&self.device, memory_types, self.batcher.indices_u16());
+            index_stream_u32.upload(&self.device, memory_types, self.batcher.indices_u32());
+            self.draw_uniform.upload(frame_idx, memory_types);
+
             command_pool.reset(false);
 
             // Rendering
@@ -315,63 +1185,147 @@ impl<B: Backend> RendererState<B> {
             };
             cmd_buffer.begin_primary(command::CommandBufferFlags::ONE_TIME_SUBMIT);
 
-            cmd_buffer.set_viewports(0, &[self.viewport.clone()]);
-            cmd_buffer.set_scissors(0, &[self.viewport.rect]);
-            cmd_buffer.bind_graphics_pipeline(self.pipeline.pipeline.as_ref().unwrap());
-            cmd_buffer.bind_vertex_buffers(
-                0,
-                Some((self.vertex_buffer.get_buffer(), buffer::SubRange::WHOLE)),
-            );
-            cmd_buffer.bind_graphics_descriptor_sets(
-                self.pipeline.pipeline_layout.as_ref().unwrap(),
-                0,
-                vec![
-                    self.image.desc.set.as_ref().unwrap(),
-                    self.uniform.desc.as_ref().unwrap().set.as_ref().unwrap(),
-                ],
-                &[],
-            ); //TODO
+            cmd_buffer.set_viewports(0, std::slice::from_ref(&self.viewport));
+            cmd_buffer.set_scissors(0, [self.viewport.rect]);
+
+            let view_proj = self.camera.map_or_else(Matrix4::identity, |camera| camera.view_projection());
+            let view_proj: &[f32; 16] = view_proj.as_ref();
+            let constants: Vec<u32> = view_proj.iter().map(|v| v.to_bits()).collect();
+            debug_assert_eq!(constants.len() * 4, PUSH_CONSTANTS_SIZE as usize);
 
             cmd_buffer.begin_render_pass(
                 self.render_pass.render_pass.as_ref().unwrap(),
-                &framebuffer,
+                framebuffer,
                 self.viewport.rect,
-                &[command::ClearValue {
+                [command::ClearValue {
                     color: command::ClearColor {
                         float32: self.bg_color,
                     },
                 }],
                 command::SubpassContents::Inline,
             );
-            cmd_buffer.draw(0..6, 0..1);
+            let mut bound = None;
+            let mut bound_pipeline = None;
+            for (&draw_call, &(texture, key)) in self.draw_calls.iter().zip(&keys) {
+                let next = match draw_call {
+                    DrawCall::Batch(i) => Bound::Stream(self.batcher.batches()[i].width),
+                    DrawCall::Mesh { mesh, wireframe, .. } => {
+                        Bound::Mesh(mesh, wireframe && get_mesh(&self.meshes, mesh).edges.is_some())
+                    }
+                };
+                let uniforms = match draw_call {
+                    DrawCall::Batch(i) => self.batcher.batches()[i].key.uniforms,
+                    DrawCall::Mesh { uniforms, .. } => uniforms,
+                };
+
+                let pipeline = match self.render_pass.pipelines.get(&key) {
+                    Some(pipeline) => pipeline,
+                    None => continue,
+                };
+                let layout = pipeline.pipeline_layout.as_ref().unwrap();
+                if bound_pipeline != Some(key) {
+                    cmd_buffer.bind_graphics_pipeline(pipeline.pipeline.as_ref().unwrap());
+                    cmd_buffer.push_graphics_constants(layout, pso::ShaderStageFlags::VERTEX, 0, &constants);
+                    bound_pipeline = Some(key);
+                }
+
+                cmd_buffer.bind_graphics_descriptor_sets(
+                    layout,
+                    0,
+                    vec![
+                        get_texture(&self.textures, texture).desc.set.as_ref().unwrap(),
+                        self.uniform.desc.as_ref().unwrap().set.as_ref().unwrap(),
+                        self.draw_uniform.desc.as_ref().unwrap().set.as_ref().unwrap(),
+                    ],
+                    &[self.draw_uniform.offset(frame_idx, uniforms)],
+                );
+
+                if bound != Some(next) {
+                    match next {
+                        Bound::Stream(width) => {
+                            let index_view = match width {
+                                IndexWidth::U16 => index_stream_u16.index_view(IndexType::U16),
+                                IndexWidth::U32 => index_stream_u32.index_view(IndexType::U32),
+                            };
+                            cmd_buffer.bind_vertex_buffers(
+                                0,
+                                Some((vertex_stream.get_buffer().unwrap(), buffer::SubRange::WHOLE)),
+                            );
+                            cmd_buffer.bind_index_buffer(index_view.unwrap());
+                        }
+                        Bound::Mesh(mesh, edges) => {
+                            let mesh = get_mesh(&self.meshes, mesh);
+                            cmd_buffer.bind_vertex_buffers(
+                                0,
+                                Some((mesh.vertices.get_buffer(), buffer::SubRange::WHOLE)),
+                            );
+                            cmd_buffer.bind_index_buffer(mesh.index_buffer(edges).view());
+                        }
+                    }
+                    bound = Some(next);
+                }
+
+                match draw_call {
+                    DrawCall::Batch(i) => {
+                        let batch = &self.batcher.batches()[i];
+                        cmd_buffer.draw_indexed(batch.indices.clone(), batch.base_vertex as i32, 0..1);
+                    }
+                    DrawCall::Mesh { mesh, wireframe, .. } => {
+                        let indices = get_mesh(&self.meshes, mesh).index_buffer(wireframe);
+                        cmd_buffer.draw_indexed(0..indices.count(), 0, 0..1);
+                    }
+                }
+            }
             cmd_buffer.end_render_pass();
             cmd_buffer.finish();
 
             let submission = Submission {
                 command_buffers: iter::once(&cmd_buffer),
                 wait_semaphores: None,
-                signal_semaphores: iter::once(&*sem_image_present),
+                signal_semaphores: surface_image.as_ref().map(|_| &*sem_image_present),
             };
 
-            self.device.borrow_mut().queues.queues[0].submit(submission, None);
+            self.device.borrow_mut().queues.queues[0].submit(submission, Some(&*fence));
             command_buffers.push(cmd_buffer);
 
             // present frame
-            if let Err(_) = self.device.borrow_mut().queues.queues[0].present(
-                &mut *self.backend.surface,
-                surface_image,
-                Some(sem_image_present),
-            ) {
-                self.recreate_swapchain = true;
+            if let Some(surface_image) = surface_image {
+                if self.device.borrow_mut().queues.queues[0].present(
+                    self.backend.surface.as_mut().unwrap(),
+                    surface_image,
+                    Some(sem_image_present),
+                ).is_err() {
+                    self.recreate_swapchain = true;
+                }
             }
 
-            self.device.borrow().device.destroy_framebuffer(framebuffer);
+            if let Some(framebuffer) = surface_framebuffer {
+                self.device.borrow().device.destroy_framebuffer(framebuffer);
+            }
         }
+
+        self.batcher.clear();
+        self.draw_calls.clear();
+        self.draw_uniform.clear();
+        self.draw_block = None;
+    }
+
+    /// Reads back the last frame drawn by a headless renderer.
+    ///
+    /// Panics if the renderer presents to a window instead.
+    pub fn read_pixels(&mut self) -> image::RgbaImage {
+        let offscreen = self
+            .offscreen
+            .as_mut()
+            .expect("read_pixels needs a renderer created with new_headless");
+
+        self.device.borrow().device.wait_idle().unwrap();
+        unsafe { offscreen.read_pixels() }
     }
 
     pub fn input(&mut self, kc: winit::event::VirtualKeyCode) {
         match kc {
-            winit::event::VirtualKeyCode::Key0 => self.cur_value = self.cur_value * 10 + 0,
+            winit::event::VirtualKeyCode::Key0 => self.cur_value *= 10,
             winit::event::VirtualKeyCode::Key1 => self.cur_value = self.cur_value * 10 + 1,
             winit::event::VirtualKeyCode::Key2 => self.cur_value = self.cur_value * 10 + 2,
             winit::event::VirtualKeyCode::Key3 => self.cur_value = self.cur_value * 10 + 3,
@@ -404,11 +1358,8 @@ impl<B: Backend> RendererState<B> {
                     Color::Blue => self.color[2] = self.cur_value as f32 / 255.0,
                     Color::Alpha => self.color[3] = self.cur_value as f32 / 255.0,
                 }
-                self.uniform
-                    .buffer
-                    .as_mut()
-                    .unwrap()
-                    .update_data(0, &self.color);
+                // Written by `end_frame` once no frame reads the uniform.
+                self.color_changed = true;
                 self.cur_value = 0;
 
                 println!("Colour updated!");
@@ -438,39 +1389,66 @@ impl<B: Backend> RendererState<B> {
     }
 }
 
+/// `bindings` with their uniform buffers read at dynamic offsets.
+fn with_dynamic_offsets(mut bindings: Vec<pso::DescriptorSetLayoutBinding>) -> Vec<pso::DescriptorSetLayoutBinding> {
+    for binding in &mut bindings {
+        if let pso::DescriptorType::Buffer {
+            ty: pso::BufferDescriptorType::Uniform,
+            format: pso::BufferDescriptorFormat::Structured { dynamic_offset },
+        } = &mut binding.ty
+        {
+            *dynamic_offset = true;
+        }
+    }
+    bindings
+}
+
 impl<B: Backend> Drop for RendererState<B> {
     fn drop(&mut self) {
+        // The fields then drop in order, `desc_allocator` after everything
+        // holding its sets. Its pools take any compute sets still out along.
         self.device.borrow().device.wait_idle().unwrap();
-        unsafe {
-            self.device
-                .borrow()
-                .device
-                .destroy_descriptor_pool(self.img_desc_pool.take().unwrap());
-            self.device
-                .borrow()
-                .device
-                .destroy_descriptor_pool(self.uniform_desc_pool.take().unwrap());
-        }
     }
 }
 
+/// The render pass together with the framebuffers built against it, so
+/// they are dropped whenever the pass is recreated.
 struct RenderPassState<B: Backend> {
     render_pass: Option<B::RenderPass>,
+    /// Pipelines only work with the pass they were built for, so they live
+    /// and die with it.
+    pipelines: PipelineCache<B>,
+    /// Framebuffer for the offscreen target, built on first use. Surface
+    /// images get a new one every frame instead: gfx-backend-vulkan 0.6 ties
+    /// framebuffers on a swapchain image to that image and destroys them when
+    /// it is next acquired, and hal 0.6 has no imageless framebuffers.
+    offscreen_framebuffer: Option<B::Framebuffer>,
     device: Rc<RefCell<DeviceState<B>>>,
 }
 
 impl<B: Backend> RenderPassState<B> {
-    unsafe fn new(swapchain: &SwapchainState, device: Rc<RefCell<DeviceState<B>>>) -> Self {
+    /// A headless pass leaves its target ready to be copied out instead of presented.
+    unsafe fn new(
+        swapchain: &SwapchainState,
+        headless: bool,
+        device: Rc<RefCell<DeviceState<B>>>,
+    ) -> Self {
+        let final_layout = if headless {
+            i::Layout::TransferSrcOptimal
+        } else {
+            i::Layout::Present
+        };
+
         let render_pass = {
             let attachment = pass::Attachment {
-                format: Some(swapchain.format.clone()),
+                format: Some(swapchain.format),
                 samples: 1,
                 ops: pass::AttachmentOps::new(
                     pass::AttachmentLoadOp::Clear,
                     pass::AttachmentStoreOp::Store,
                 ),
                 stencil_ops: pass::AttachmentOps::DONT_CARE,
-                layouts: i::Layout::Undefined..i::Layout::Present,
+                layouts: i::Layout::Undefined..final_layout,
             };
 
             let subpass = pass::SubpassDesc {
@@ -490,15 +1468,41 @@ impl<B: Backend> RenderPassState<B> {
 
         RenderPassState {
             render_pass,
+            offscreen_framebuffer: None,
+            pipelines: PipelineCache::new(Rc::clone(&device)),
             device,
         }
     }
+
+    /// Creates the framebuffer drawing into the offscreen `attachment`
+    /// unless it already exists.
+    unsafe fn cache_offscreen_framebuffer(&mut self, attachment: &B::ImageView, extent: i::Extent) {
+        if self.offscreen_framebuffer.is_some() {
+            return;
+        }
+
+        let framebuffer = self
+            .device
+            .borrow()
+            .device
+            .create_framebuffer(self.render_pass.as_ref().unwrap(), iter::once(attachment), extent)
+            .unwrap();
+        self.offscreen_framebuffer = Some(framebuffer);
+    }
+
+    fn get_offscreen_framebuffer(&self) -> &B::Framebuffer {
+        self.offscreen_framebuffer.as_ref().unwrap()
+    }
 }
 
 impl<B: Backend> Drop for RenderPassState<B> {
     fn drop(&mut self) {
         let device = &self.device.borrow().device;
+        self.pipelines.clear();
         unsafe {
+            if let Some(framebuffer) = self.offscreen_framebuffer.take() {
+                device.destroy_framebuffer(framebuffer);
+            }
             device.destroy_render_pass(self.render_pass.take().unwrap());
         }
     }
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    io::Cursor,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use hal::pso::ShaderStageFlags;
+
+#[cfg(feature = "dev-shaders")]
+use std::iter;
+
+#[cfg(feature = "dev-shaders")]
+use crate::preprocess::Preprocessor;
+
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// Directory the shader sources live in, for the `dev-shaders` feature.
+pub const SOURCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+/// SPIR-V compiled by the build script for the shader file `name`, e.g.
+/// `"quad.vert"`, or for a variant by its `variant_key`.
+pub fn embedded(name: &str) -> Option<&'static [u8]> {
+    embedded::SHADERS
+        .iter()
+        .find(|(shader, _)| *shader == name)
+        .map(|(_, spirv)| *spirv)
+}
+
+/// Why the SPIR-V of a shader file couldn't be had.
+#[derive(Debug)]
+pub enum ShaderError {
+    /// No shader file, or no embedded shader, of that name.
+    NotFound { name: String },
+    /// The extension doesn't name a stage, e.g. an include file.
+    UnknownStage { name: String },
+    /// An include is missing or malformed.
+    Preprocess { path: PathBuf, message: String },
+    /// The compiler rejected the source. The log points into the original
+    /// files rather than the preprocessed code.
+    Compile {
+        stage: ShaderStageFlags,
+        path: PathBuf,
+        log: String,
+    },
+    /// The compiled module isn't valid SPIR-V.
+    InvalidSpirv { name: String, error: io::Error },
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::NotFound { name } => write!(f, "no shader named {}", name),
+            ShaderError::UnknownStage { name } => write!(f, "unknown shader stage of {}", name),
+            ShaderError::Preprocess { path, message } => {
+                write!(f, "can't preprocess {}: {}", path.display(), message)
+            }
+            ShaderError::Compile { stage, path, log } => {
+                write!(f, "{:?} shader {} doesn't compile:\n{}", stage, path.display(), log)
+            }
+            ShaderError::InvalidSpirv { name, error } => write!(f, "{}: {}", name, error),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShaderError::InvalidSpirv { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The stage shader file `name` is for, from its extension.
+pub fn stage(name: &str) -> Option<ShaderStageFlags> {
+    match name.rsplit('.').next() {
+        Some("vert") => Some(ShaderStageFlags::VERTEX),
+        Some("frag") => Some(ShaderStageFlags::FRAGMENT),
+        Some("comp") => Some(ShaderStageFlags::COMPUTE),
+        _ => None,
+    }
+}
+
+/// Whether `name` is a shader stage rather than a file to be included.
+pub fn is_stage(name: &str) -> bool {
+    stage(name).is_some()
+}
+
+/// Loads the SPIR-V for shader file `name`, or describes why it can't.
+///
+/// With the `dev-shaders` feature the source in `SOURCE_DIR` is compiled on
+/// the spot, otherwise the embedded build is used.
+pub fn try_load(name: &str) -> Result<Vec<u32>, ShaderError> {
+    try_load_variant(name, &[])
+}
+
+/// Loads the SPIR-V of shader file `name` compiled with `defines`.
+///
+/// Without the `dev-shaders` feature only the variants declared in
+/// `variants::VARIANTS` are available, since they are built ahead of time.
+pub fn try_load_variant(name: &str, defines: &[(&str, &str)]) -> Result<Vec<u32>, ShaderError> {
+    #[cfg(feature = "dev-shaders")]
+    {
+        compile_variant(name, defines)
+    }
+    #[cfg(not(feature = "dev-shaders"))]
+    {
+        let key = crate::variants::variant_key(name, defines);
+        let spirv = embedded(&key).ok_or(ShaderError::NotFound { name: key })?;
+        auxil::read_spirv(Cursor::new(spirv)).map_err(|error| ShaderError::InvalidSpirv {
+            name: name.to_string(),
+            error,
+        })
+    }
+}
+
+/// Like `try_load`, but panics on failure.
+pub fn load(name: &str) -> Vec<u32> {
+    try_load(name).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Compiles shader file `name` from `SOURCE_DIR` with `defines`, for
+/// variants of a shader, whether declared in `variants::VARIANTS` or not.
+#[cfg(feature = "dev-shaders")]
+pub fn compile_variant(name: &str, defines: &[(&str, &str)]) -> Result<Vec<u32>, ShaderError> {
+    compile_file(Path::new(SOURCE_DIR).join(name), &[], defines)
+}
+
+/// Compiles the shader file at `path` with `defines`. The file may live
+/// anywhere, e.g. in a directory of your own shaders.
+///
+/// Includes are looked up next to the including file, then in
+/// `include_dirs` in order, then in `SOURCE_DIR`, the same search path the
+/// build script uses, so your shaders can include the ones shipped here.
+/// Compile errors point into the original files.
+#[cfg(feature = "dev-shaders")]
+pub fn compile_file<P: AsRef<Path>>(
+    path: P,
+    include_dirs: &[&Path],
+    defines: &[(&str, &str)],
+) -> Result<Vec<u32>, ShaderError> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+    let stage = stage(&name).ok_or_else(|| ShaderError::UnknownStage { name: name.clone() })?;
+    let ty = match stage {
+        ShaderStageFlags::VERTEX => glsl_to_spirv::ShaderType::Vertex,
+        ShaderStageFlags::FRAGMENT => glsl_to_spirv::ShaderType::Fragment,
+        _ => glsl_to_spirv::ShaderType::Compute,
+    };
+    if !path.is_file() {
+        return Err(ShaderError::NotFound { name });
+    }
+
+    let preprocessor = include_dirs
+        .iter()
+        .chain(iter::once(&Path::new(SOURCE_DIR)))
+        .fold(Preprocessor::new(), |p, dir| p.include_dir(dir));
+    let preprocessor = defines
+        .iter()
+        .fold(preprocessor, |p, (name, value)| p.define(name, value));
+    let source = preprocessor
+        .process_file(path)
+        .map_err(|message| ShaderError::Preprocess {
+            path: path.to_path_buf(),
+            message,
+        })?;
+    let file = glsl_to_spirv::compile(&source.code, ty).map_err(|log| ShaderError::Compile {
+        stage,
+        log: source.map_log(&log),
+        path: path.to_path_buf(),
+    })?;
+    auxil::read_spirv(file).map_err(|error| ShaderError::InvalidSpirv { name, error })
+}
+
+/// Notices shader sources being edited by polling their modification times.
+#[derive(Debug)]
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    mtimes: HashMap<String, SystemTime>,
+}
+
+impl ShaderWatcher {
+    /// Starts watching the files in `dir`, taking their current state as
+    /// unchanged.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        let mut watcher = ShaderWatcher {
+            dir: dir.as_ref().to_path_buf(),
+            mtimes: HashMap::new(),
+        };
+        watcher.poll();
+        watcher
+    }
+
+    /// Names of the files created or modified since the last call.
+    pub fn poll(&mut self) -> Vec<String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut changed = Vec::new();
+        for entry in entries.flatten() {
+            let mtime = match entry.metadata().and_then(|m| m.modified()) {
+                Ok(mtime) => mtime,
+                Err(_) => continue,
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if self.mtimes.insert(name.clone(), mtime) != Some(mtime) {
+                changed.push(name);
+            }
+        }
+        changed.sort();
+        changed
+    }
+}
@@ -0,0 +1,8 @@
+#pragma once
+
+// Per-draw data, read at a dynamic offset. Matches `pipeline::DrawUniforms`.
+layout(set = 2, binding = 0) uniform Draw {
+    mat4 transform;
+    vec4 tint;
+    float time;
+} draw;
@@ -2,6 +2,7 @@
 #extension GL_ARB_separate_shader_objects : enable
 
 layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_color;
 layout(location = 0) out vec4 target0;
 
 layout(set = 0, binding = 0) uniform texture2D u_texture;
@@ -11,7 +12,9 @@ layout(set = 1, binding = 0) uniform UBOCol {
     vec4 color;
 } color_dat;
 
+#include "draw.glsl"
+
 void main() {
     vec4 color = vec4(color_dat.color.r * color_dat.color.a, color_dat.color.g * color_dat.color.a, color_dat.color.b * color_dat.color.a, color_dat.color.a);
-    target0 = texture(sampler2D(u_texture, u_sampler), v_uv) * color;
+    target0 = texture(sampler2D(u_texture, u_sampler), v_uv) * v_color * color * draw.tint;
 }
@@ -0,0 +1,29 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(constant_id = 0) const float scale = 1.2f;
+
+layout(location = 0) in vec2 a_pos;
+layout(location = 1) in vec2 a_uv;
+layout(location = 2) in vec4 a_color;
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec4 v_color;
+
+layout(push_constant) uniform Camera {
+    mat4 view_proj;
+} camera;
+
+#include "draw.glsl"
+
+out gl_PerVertex {
+    vec4 gl_Position;
+    float gl_PointSize;
+};
+
+void main() {
+    v_uv = a_uv;
+    v_color = a_color;
+    gl_Position = camera.view_proj * draw.transform * vec4(scale * a_pos, 0.0, 1.0);
+    // Only read when drawing point lists, which leave it undefined otherwise.
+    gl_PointSize = 1.0;
+}
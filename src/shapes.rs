@@ -1,39 +1,111 @@
 //use std::ops::{Add, Sub, Mul, Div};
 use std::fmt::Debug;
 
-use cgmath::Vector2;
+use cgmath::{BaseFloat, InnerSpace, Vector2};
+
+use hal::pso::Primitive;
 
 use rgb::RGBA8;
 
-pub trait Shape : Debug + Send + Sync {
-    fn center(self) -> Vector2<f32>;
-    fn area(self) -> f32;
+/// Geometry is computed in `S` (`f32` unless asked otherwise), while
+/// `vertexes` always produces `f32` output for the GPU.
+///
+/// All point queries mean the same thing on every shape: `centroid` is the
+/// center of mass of the filled area and `bounding_center` is the middle of
+/// the axis-aligned `bounds`.
+pub trait Shape<S: BaseFloat = f32> : Debug + Copy + Send + Sync {
+    /// Same as `centroid`.
+    fn center(self) -> Vector2<S> {
+        self.centroid()
+    }
+    fn centroid(self) -> Vector2<S>;
+    /// Axis-aligned bounding box as `(min, max)`.
+    fn bounds(self) -> (Vector2<S>, Vector2<S>);
+    fn bounding_center(self) -> Vector2<S> {
+        let (min, max) = self.bounds();
+        (min + max) / two()
+    }
+
+    fn area(self) -> S;
+    fn perimeter(self) -> S;
+    /// Second moment of area about the centroid.
+    fn second_moment(self) -> SecondMoment<S>;
 
     fn color(self, c: RGBA8) -> Self;
-    fn format(self, f: ShapeFormat) -> Self;
+    fn format(self, f: ShapeFormat<S>) -> Self;
+    fn get_color(self) -> RGBA8;
 
-    fn contains(self, v: Vector2<f32>) -> bool;
+    fn contains(self, v: Vector2<S>) -> bool;
 
     fn vertexes(self) -> (Vec<Vector2<f32>>, Option<Vec<u16>>);
+
+    /// How the output of `vertexes` is assembled into primitives.
+    fn topology(self) -> Primitive {
+        Primitive::TriangleList
+    }
+}
+
+/// Converts a point to the `f32` precision used for vertex output.
+///
+/// This is exact for `f32` shapes; `f64` coordinates are rounded to the
+/// nearest `f32`, so geometry queries keep full precision and only the
+/// rendered positions are narrowed.
+pub fn to_f32<S: BaseFloat>(v: Vector2<S>) -> Vector2<f32> {
+    v.cast().expect("float to f32 conversion can't fail")
+}
+
+fn two<S: BaseFloat>() -> S {
+    S::one() + S::one()
+}
+
+/// Second moment of area (area moment of inertia) about axes through the
+/// centroid, parallel to x and y.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct SecondMoment<S = f32> {
+    /// About the x axis, `∫ y² dA`.
+    pub ix: S,
+    /// About the y axis, `∫ x² dA`.
+    pub iy: S,
+    /// Product of inertia, `∫ xy dA`.
+    pub ixy: S,
+}
+
+impl<S: BaseFloat> SecondMoment<S> {
+    /// Polar moment about the centroid, `ix + iy`.
+    pub fn polar(self) -> S {
+        self.ix + self.iy
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-pub enum ShapeFormat {
+pub enum ShapeFormat<S = f32> {
     Fill,
-    Line(f32),
+    Line(S),
+    /// The outline as 1 pixel wide GPU lines, whatever the zoom.
+    Hairline,
+}
+
+impl<S: BaseFloat> ShapeFormat<S> {
+    pub fn cast<T: BaseFloat>(self) -> Option<ShapeFormat<T>> {
+        match self {
+            ShapeFormat::Fill => Some(ShapeFormat::Fill),
+            ShapeFormat::Line(width) => T::from(width).map(ShapeFormat::Line),
+            ShapeFormat::Hairline => Some(ShapeFormat::Hairline),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-pub struct Rectangle {
-    pub position: Vector2<f32>,
-    pub wh: Vector2<f32>,
+pub struct Rectangle<S = f32> {
+    pub position: Vector2<S>,
+    pub wh: Vector2<S>,
 
     pub color: RGBA8,
-    pub format: ShapeFormat,
+    pub format: ShapeFormat<S>,
 }
 
-impl Rectangle {
-    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Rectangle {
+impl<S: BaseFloat> Rectangle<S> {
+    pub fn new(x: S, y: S, w: S, h: S) -> Rectangle<S> {
         Rectangle {
             position: Vector2::new(x, y),
             wh: Vector2::new(w, h),
@@ -41,15 +113,48 @@ impl Rectangle {
             format: ShapeFormat::Fill,
         }
     }
+
+    /// Converts the rectangle to another scalar type, e.g. `f64` geometry
+    /// down to `f32` or back up.
+    pub fn cast<T: BaseFloat>(self) -> Option<Rectangle<T>> {
+        Some(Rectangle {
+            position: self.position.cast()?,
+            wh: self.wh.cast()?,
+            color: self.color,
+            format: self.format.cast()?,
+        })
+    }
+}
+
+impl From<Rectangle<f32>> for Rectangle<f64> {
+    fn from(r: Rectangle<f32>) -> Self {
+        r.cast().unwrap()
+    }
 }
 
-impl Shape for Rectangle {
-    fn center(self) -> Vector2<f32> {
-        self.position + (self.wh / 2f32)
+impl<S: BaseFloat + Send + Sync> Shape<S> for Rectangle<S> {
+    fn centroid(self) -> Vector2<S> {
+        self.position + (self.wh / two())
     }
-    fn area(self) -> f32 {
+    fn bounds(self) -> (Vector2<S>, Vector2<S>) {
+        (self.position, self.position + self.wh)
+    }
+
+    fn area(self) -> S {
         self.wh.x * self.wh.y
     }
+    fn perimeter(self) -> S {
+        two::<S>() * (self.wh.x + self.wh.y)
+    }
+    fn second_moment(self) -> SecondMoment<S> {
+        let twelve = S::from(12).unwrap();
+        let (w, h) = (self.wh.x, self.wh.y);
+        SecondMoment {
+            ix: w * h * h * h / twelve,
+            iy: h * w * w * w / twelve,
+            ixy: S::zero(),
+        }
+    }
 
     fn color(self, c: RGBA8) -> Self {
         Rectangle {
@@ -59,7 +164,7 @@ impl Shape for Rectangle {
             format: self.format,
         }
     }
-    fn format(self, f: ShapeFormat) -> Self {
+    fn format(self, f: ShapeFormat<S>) -> Self {
         Rectangle {
             position: self.position,
             wh: self.wh,
@@ -67,22 +172,28 @@ impl Shape for Rectangle {
             format: f,
         }
     }
+    fn get_color(self) -> RGBA8 {
+        self.color
+    }
 
-    fn contains(self, v: Vector2<f32>) -> bool {
+    fn contains(self, v: Vector2<S>) -> bool {
         !(v.x < self.position.x || v.x > self.position.x + self.wh.x || v.y < self.position.y || v.y > self.position.y + self.wh.y)
     }
 
     fn vertexes(self) -> (Vec<Vector2<f32>>, Option<Vec<u16>>){
         match self.format {
-            ShapeFormat::Fill => {
+            ShapeFormat::Fill | ShapeFormat::Hairline => {
                 (
                     vec![
-                        self.position,
-                        self.position + Vector2::new(self.wh.x, 0f32),
-                        self.position + Vector2::new(0f32, self.wh.y),
-                        self.position + self.wh
+                        to_f32(self.position),
+                        to_f32(self.position + Vector2::new(self.wh.x, S::zero())),
+                        to_f32(self.position + self.wh),
+                        to_f32(self.position + Vector2::new(S::zero(), self.wh.y))
                     ],
-                    Some(vec![0, 1, 2, 0, 2, 3])
+                    Some(match self.format {
+                        ShapeFormat::Hairline => vec![0, 1, 1, 2, 2, 3, 3, 0],
+                        _ => vec![0, 1, 2, 0, 2, 3],
+                    })
                 )
             },
             ShapeFormat::Line(_width) => {
@@ -112,6 +223,13 @@ impl Shape for Rectangle {
             }
         }
     }
+
+    fn topology(self) -> Primitive {
+        match self.format {
+            ShapeFormat::Hairline => Primitive::LineList,
+            _ => Primitive::TriangleList,
+        }
+    }
 }
 
 #[macro_export]
@@ -143,7 +261,7 @@ macro_rules! rec {
     ($( $w:expr , $h:expr)*) => {
         {
             $(
-                Rectangle::new(0f32, 0f32, $w, $h)
+                Rectangle::new(Default::default(), Default::default(), $w, $h)
             )*
         }
     };
@@ -151,7 +269,7 @@ macro_rules! rec {
     ($( $w:expr , $h:expr, $c:expr)*) => {
         {
             $(
-                Rectangle::new(0f32, 0f32, $w, $h).color(c)
+                Rectangle::new(Default::default(), Default::default(), $w, $h).color(c)
             )*
         }
     };
@@ -159,7 +277,7 @@ macro_rules! rec {
     ($( $w:expr , $h:expr, $f:expr)*) => {
         {
             $(
-                Rectangle::new(0f32, 0f32, $w, $h).format(f)
+                Rectangle::new(Default::default(), Default::default(), $w, $h).format(f)
             )*
         }
     };
@@ -196,17 +314,17 @@ macro_rules! rec {
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-pub struct Triangle {
-    pub a: Vector2<f32>,
-    pub b: Vector2<f32>,
-    pub c: Vector2<f32>,
+pub struct Triangle<S = f32> {
+    pub a: Vector2<S>,
+    pub b: Vector2<S>,
+    pub c: Vector2<S>,
 
     pub color: RGBA8,
-    pub format: ShapeFormat,
+    pub format: ShapeFormat<S>,
 }
 
-impl Triangle {
-    pub fn new(a: Vector2<f32>, b: Vector2<f32>, c: Vector2<f32>) -> Triangle {
+impl<S: BaseFloat> Triangle<S> {
+    pub fn new(a: Vector2<S>, b: Vector2<S>, c: Vector2<S>) -> Triangle<S> {
         Triangle {
             a,
             b,
@@ -216,17 +334,58 @@ impl Triangle {
             format: ShapeFormat::Fill,
         }
     }
+
+    /// Converts the triangle to another scalar type, e.g. `f64` geometry
+    /// down to `f32` or back up.
+    pub fn cast<T: BaseFloat>(self) -> Option<Triangle<T>> {
+        Some(Triangle {
+            a: self.a.cast()?,
+            b: self.b.cast()?,
+            c: self.c.cast()?,
+            color: self.color,
+            format: self.format.cast()?,
+        })
+    }
 }
 
-impl Shape for Triangle {
-    fn center(self) -> Vector2<f32> {
-        (self.a + self.b + self.c) / 3f32
+impl From<Triangle<f32>> for Triangle<f64> {
+    fn from(t: Triangle<f32>) -> Self {
+        t.cast().unwrap()
     }
-    fn area(self) -> f32 {
+}
+
+impl<S: BaseFloat + Send + Sync> Shape<S> for Triangle<S> {
+    fn centroid(self) -> Vector2<S> {
+        (self.a + self.b + self.c) / (two::<S>() + S::one())
+    }
+    fn bounds(self) -> (Vector2<S>, Vector2<S>) {
+        (
+            Vector2::new(self.a.x.min(self.b.x).min(self.c.x), self.a.y.min(self.b.y).min(self.c.y)),
+            Vector2::new(self.a.x.max(self.b.x).max(self.c.x), self.a.y.max(self.b.y).max(self.c.y)),
+        )
+    }
+
+    fn area(self) -> S {
         ((self.a.x * (self.b.y - self.c.y)
         + self.b.x * (self.c.y - self.a.y)
         + self.c.x * (self.a.y - self.b.y)
-        ) / 2f32).abs()
+        ) / two()).abs()
+    }
+    fn perimeter(self) -> S {
+        (self.b - self.a).magnitude() + (self.c - self.b).magnitude() + (self.a - self.c).magnitude()
+    }
+    fn second_moment(self) -> SecondMoment<S> {
+        // With the vertices relative to the centroid a triangle's moments
+        // reduce to A/12 times the sum of the squared (or multiplied)
+        // vertex coordinates.
+        let g = self.centroid();
+        let (a, b, c) = (self.a - g, self.b - g, self.c - g);
+        let k = self.area() / S::from(12).unwrap();
+        SecondMoment {
+            ix: k * (a.y * a.y + b.y * b.y + c.y * c.y),
+            iy: k * (a.x * a.x + b.x * b.x + c.x * c.x),
+            ixy: k * (a.x * a.y + b.x * b.y + c.x * c.y),
+        }
     }
 
     fn color(self, c: RGBA8) -> Self {
@@ -238,7 +397,7 @@ impl Shape for Triangle {
             format: self.format,
         }
     }
-    fn format(self, f: ShapeFormat) -> Self {
+    fn format(self, f: ShapeFormat<S>) -> Self {
         Triangle {
             a: self.a,
             b: self.b,
@@ -247,21 +406,27 @@ impl Shape for Triangle {
             format: f,
         }
     }
+    fn get_color(self) -> RGBA8 {
+        self.color
+    }
 
-    fn contains(self, v: Vector2<f32>) -> bool {
+    fn contains(self, v: Vector2<S>) -> bool {
         self.area() == Triangle::new(v, self.b, self.c).area() + Triangle::new(self.a, v, self.c).area() + Triangle::new(self.a, self.b, v).area()
     }    
 
     fn vertexes(self) -> (Vec<Vector2<f32>>, Option<Vec<u16>>) {
         match self.format {
-            ShapeFormat::Fill => {
+            ShapeFormat::Fill | ShapeFormat::Hairline => {
                 (
                     vec![
-                        self.a,
-                        self.b,
-                        self.c
+                        to_f32(self.a),
+                        to_f32(self.b),
+                        to_f32(self.c)
                     ],
-                    None
+                    match self.format {
+                        ShapeFormat::Hairline => Some(vec![0, 1, 1, 2, 2, 0]),
+                        _ => None,
+                    }
                 )
             },
             ShapeFormat::Line(_width) => {
@@ -269,6 +434,13 @@ impl Shape for Triangle {
             }
         }
     }
+
+    fn topology(self) -> Primitive {
+        match self.format {
+            ShapeFormat::Hairline => Primitive::LineList,
+            _ => Primitive::TriangleList,
+        }
+    }
 }
 
 #[macro_export]
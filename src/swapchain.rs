@@ -29,7 +29,7 @@ impl SwapchainState {
             formats
                 .iter()
                 .find(|format| format.base_format().1 == f::ChannelType::Srgb)
-                .map(|format| *format)
+                .copied()
                 .unwrap_or(formats[0])
         });
 
@@ -49,6 +49,16 @@ impl SwapchainState {
         }
     }
 
+    /// Describes an offscreen target of a fixed size, with no surface behind it.
+    pub fn headless(extent: w::Extent2D, format: f::Format) -> Self {
+        SwapchainState {
+            extent: extent.to_extent(),
+            format,
+            frame_index: 0,
+            frame_queue_size: 1,
+        }
+    }
+
     pub fn make_viewport(&self) -> pso::Viewport {
         pso::Viewport {
             rect: pso::Rect {
@@ -0,0 +1,27 @@
+//! Shader variants the build script compiles and embeds next to each shader
+//! file, so they can be loaded without the `dev-shaders` feature.
+//!
+//! Only uses `std`, since the build script compiles it in as well.
+
+/// Shader files and the defines of each variant built from them.
+///
+/// Only for variants the library itself uses; with `dev-shaders`, or
+/// `shader::compile_file`, any variant can be compiled at runtime.
+pub const VARIANTS: &[(&str, &[(&str, &str)])] = &[];
+
+/// Name the variant of shader file `name` with `defines` is embedded under,
+/// e.g. `"blur.comp?RADIUS=4"`. Without defines it is just `name`.
+///
+/// Defines are sorted, so the order they are given in doesn't matter.
+pub fn variant_key(name: &str, defines: &[(&str, &str)]) -> String {
+    let mut defines: Vec<String> = defines
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    defines.sort();
+    if defines.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}?{}", name, defines.join("&"))
+    }
+}
@@ -0,0 +1,79 @@
+use std::mem::size_of;
+
+use cgmath::{Point2, Point3, Vector2, Vector3, Vector4};
+use hal::pso;
+use rgb::{RGBA, RGBA8};
+
+pub use core_derive::VertexLayout;
+pub use hal::format::Format;
+pub use hal::pso::AttributeDesc;
+
+/// The attribute format a field type is read with.
+///
+/// Byte colors are normalized, other integers are read as integers.
+pub trait VertexFormat {
+    const FORMAT: Format;
+}
+
+macro_rules! vertex_formats {
+    ($($ty:ty => $format:ident,)*) => {
+        $(
+            impl VertexFormat for $ty {
+                const FORMAT: Format = Format::$format;
+            }
+        )*
+    };
+}
+
+vertex_formats! {
+    f32 => R32Sfloat,
+    [f32; 2] => Rg32Sfloat,
+    [f32; 3] => Rgb32Sfloat,
+    [f32; 4] => Rgba32Sfloat,
+    Vector2<f32> => Rg32Sfloat,
+    Vector3<f32> => Rgb32Sfloat,
+    Vector4<f32> => Rgba32Sfloat,
+    Point2<f32> => Rg32Sfloat,
+    Point3<f32> => Rgb32Sfloat,
+    u32 => R32Uint,
+    [u32; 2] => Rg32Uint,
+    [u32; 3] => Rgb32Uint,
+    [u32; 4] => Rgba32Uint,
+    i32 => R32Sint,
+    [i32; 2] => Rg32Sint,
+    [i32; 3] => Rgb32Sint,
+    [i32; 4] => Rgba32Sint,
+    [u8; 4] => Rgba8Uint,
+    RGBA8 => Rgba8Unorm,
+    RGBA<u16> => Rgba16Unorm,
+    RGBA<f32> => Rgba32Sfloat,
+}
+
+/// Describes how a vertex type is laid out in a vertex buffer.
+///
+/// Derive it rather than implementing it by hand, see `core_derive`. The
+/// derive needs `#[vertex(crate = "::core")]`, or however the crate is named.
+pub trait VertexLayout: Sized {
+    /// One attribute per field, at consecutive locations.
+    fn attributes(binding: pso::BufferIndex) -> Vec<AttributeDesc>;
+
+    fn buffer_desc(binding: pso::BufferIndex) -> pso::VertexBufferDesc {
+        pso::VertexBufferDesc {
+            binding,
+            stride: size_of::<Self>() as u32,
+            rate: pso::VertexInputRate::Vertex,
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn attribute(location: pso::Location, binding: pso::BufferIndex, format: Format, offset: usize) -> AttributeDesc {
+    AttributeDesc {
+        location,
+        binding,
+        element: pso::Element {
+            format,
+            offset: offset as u32,
+        },
+    }
+}
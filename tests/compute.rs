@@ -0,0 +1,162 @@
+//! Compute dispatches checked on the CPU.
+//!
+//! These need a Vulkan adapter, so they are ignored by default.
+//! `scripts/gpu-tests.sh` runs them on lavapipe, as CI does.
+
+use image::{Rgba, RgbaImage};
+
+use core::backend::{create_headless_backend, DefaultBackend};
+use core::compute::ComputeResource;
+use core::render::RendererState;
+use core::variants::variant_key;
+
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/tests/shaders.rs"));
+}
+
+/// SPIR-V of a shader in `tests/shaders`, or of a variant of one that
+/// `build.rs` declares.
+fn shader(name: &str, defines: &[(&str, &str)]) -> Vec<u32> {
+    let key = variant_key(name, defines);
+    let (_, spirv) = shaders::TEST_SHADERS
+        .iter()
+        .find(|(shader, _)| *shader == key)
+        .unwrap_or_else(|| panic!("no test shader {}", key));
+    auxil::read_spirv(std::io::Cursor::new(spirv)).unwrap()
+}
+
+/// A headless renderer, failing the test when there is no adapter.
+fn renderer() -> RendererState<DefaultBackend> {
+    let backend = create_headless_backend().expect("no Vulkan adapter available");
+    let extent = hal::window::Extent2D {
+        width: 4,
+        height: 4,
+    };
+    unsafe { RendererState::new_headless(backend, extent) }
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn scale_buffer() {
+    let mut renderer = renderer();
+
+    let values: Vec<f32> = (0..100).map(|i| i as f32).collect();
+    let mut buffer = renderer.create_storage_buffer(4 * values.len() as u64);
+    buffer.write(0, &values);
+
+    let pipeline = renderer
+        .create_compute_pipeline_from_spirv(&shader("scale.comp", &[]))
+        .unwrap();
+    let set = renderer
+        .create_compute_set(&pipeline, 0, &[(0, ComputeResource::Buffer(&buffer))])
+        .unwrap();
+    renderer.dispatch(
+        &pipeline,
+        &[&set],
+        &[2.5f32.to_bits(), values.len() as u32],
+        [2, 1, 1],
+    );
+
+    let mut scaled = vec![0f32; values.len()];
+    buffer.read(0, &mut scaled);
+    let expected: Vec<f32> = values.iter().map(|v| v * 2.5).collect();
+    assert_eq!(expected, scaled);
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn invert_image() {
+    let mut renderer = renderer();
+
+    let input = RgbaImage::from_fn(10, 6, |x, y| Rgba([x as u8 * 20, y as u8 * 40, 7, 200]));
+    let mut image = renderer.create_storage_image(10, 6, Some(&input));
+
+    let pipeline = renderer
+        .create_compute_pipeline_from_spirv(&shader("invert.comp", &[]))
+        .unwrap();
+    assert!(renderer
+        .create_compute_set(&pipeline, 0, &[(1, ComputeResource::Image(&image))])
+        .is_err());
+    let set = renderer
+        .create_compute_set(&pipeline, 0, &[(0, ComputeResource::Image(&image))])
+        .unwrap();
+    renderer.dispatch(&pipeline, &[&set], &[], [2, 1, 1]);
+
+    let output = unsafe { image.read_pixels() };
+    for (x, y, pixel) in output.enumerate_pixels() {
+        let Rgba([r, g, b, a]) = *input.get_pixel(x, y);
+        assert_eq!(
+            Rgba([255 - r, 255 - g, 255 - b, a]),
+            *pixel,
+            "at {}, {}",
+            x,
+            y
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn many_descriptor_sets() {
+    let mut renderer = renderer();
+
+    // More than fit in one descriptor pool.
+    let texture = RgbaImage::from_pixel(2, 2, Rgba([1, 2, 3, 4]));
+    let textures: Vec<_> = (0..100).map(|_| renderer.create_texture(&texture)).collect();
+    let pools = renderer.get_desc_pool_count();
+    assert!(pools > 1);
+
+    // Freed sets are reused rather than adding pools.
+    for texture in textures {
+        renderer.destroy_texture(texture);
+    }
+    let textures: Vec<_> = (0..100).map(|_| renderer.create_texture(&texture)).collect();
+    assert_eq!(pools, renderer.get_desc_pool_count());
+
+    let buffer = renderer.create_storage_buffer(16);
+    let pipeline = renderer
+        .create_compute_pipeline_from_spirv(&shader("scale.comp", &[]))
+        .unwrap();
+    let mut after_first_round = None;
+    for _ in 0..3 {
+        let sets: Vec<_> = (0..100)
+            .map(|_| {
+                renderer
+                    .create_compute_set(&pipeline, 0, &[(0, ComputeResource::Buffer(&buffer))])
+                    .unwrap()
+            })
+            .collect();
+        for set in sets {
+            renderer.free_compute_set(set);
+        }
+        let count = renderer.get_desc_pool_count();
+        assert_eq!(count, *after_first_round.get_or_insert(count));
+    }
+
+    for texture in textures {
+        renderer.destroy_texture(texture);
+    }
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn invert_image_variant() {
+    let mut renderer = renderer();
+
+    let input = RgbaImage::from_fn(4, 4, |x, y| Rgba([x as u8 * 60, y as u8 * 60, 7, 200]));
+    let mut image = renderer.create_storage_image(4, 4, Some(&input));
+
+    let pipeline = renderer
+        .create_compute_pipeline_from_spirv(&shader("invert.comp", &[("INVERT_ALPHA", "1")]))
+        .unwrap();
+    let set = renderer
+        .create_compute_set(&pipeline, 0, &[(0, ComputeResource::Image(&image))])
+        .unwrap();
+    renderer.dispatch(&pipeline, &[&set], &[], [1, 1, 1]);
+
+    let output = unsafe { image.read_pixels() };
+    for (x, y, pixel) in output.enumerate_pixels() {
+        let Rgba([r, g, b, a]) = *input.get_pixel(x, y);
+        assert_eq!(Rgba([255 - r, 255 - g, 255 - b, 255 - a]), *pixel, "at {}, {}", x, y);
+    }
+}
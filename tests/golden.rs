@@ -0,0 +1,156 @@
+//! Offscreen renders compared against the references in `tests/golden`.
+//!
+//! These need a Vulkan adapter, so they are ignored by default.
+//! `scripts/gpu-tests.sh` runs them on lavapipe, as CI does, and
+//! `scripts/gpu-tests.sh --bless` writes missing or changed references from
+//! the render.
+
+use cgmath::{Matrix4, Vector2, Vector3};
+use image::{Rgba, RgbaImage};
+use rgb::RGBA8;
+
+use core::golden::{render_headless, Golden, Tolerance};
+use core::pipeline::{BlendMode, DrawUniforms};
+use core::render::RendererState;
+use core::shapes::{Rectangle, Shape, Triangle};
+
+const SIZE: u32 = 64;
+
+fn golden() -> Golden {
+    Golden::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden"))
+}
+
+/// Renders `scene` and checks it against the reference called `name`.
+fn check<F>(name: &str, tolerance: Tolerance, scene: F)
+where
+    F: FnOnce(&mut RendererState<core::backend::DefaultBackend>),
+{
+    check_sized(name, (SIZE, SIZE), tolerance, scene)
+}
+
+fn check_sized<F>(name: &str, (width, height): (u32, u32), tolerance: Tolerance, scene: F)
+where
+    F: FnOnce(&mut RendererState<core::backend::DefaultBackend>),
+{
+    let image = render(width, height, scene);
+    if let Err(e) = golden().tolerance(tolerance).check(name, &image) {
+        panic!("{}: {}", name, e);
+    }
+}
+
+/// Renders `scene`, failing the test when there is no adapter to render with.
+fn render<F>(width: u32, height: u32, scene: F) -> RgbaImage
+where
+    F: FnOnce(&mut RendererState<core::backend::DefaultBackend>),
+{
+    render_headless(width, height, scene).expect("no Vulkan adapter available")
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn rectangle() {
+    check("rectangle", Tolerance::default(), |r| {
+        r.draw_shape(&Rectangle::new(-0.5, -0.5, 1.0, 1.0).color(RGBA8::new(255, 0, 0, 255)));
+    });
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn triangle() {
+    let tolerance = Tolerance {
+        max_differing_pixels: 8,
+        ..Tolerance::default()
+    };
+    check("triangle", tolerance, |r| {
+        r.draw_shape(
+            &Triangle::new(
+                Vector2::new(-0.75, 0.75),
+                Vector2::new(0.75, 0.75),
+                Vector2::new(0.0, -0.75),
+            )
+            .color(RGBA8::new(0, 255, 0, 255)),
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn textured_quad() {
+    let checker = RgbaImage::from_fn(4, 4, |x, y| {
+        if (x + y) % 2 == 0 {
+            Rgba([255, 255, 255, 255])
+        } else {
+            Rgba([0, 0, 255, 255])
+        }
+    });
+    let tolerance = Tolerance {
+        per_channel: 3,
+        max_differing_pixels: 16,
+    };
+    check("textured_quad", tolerance, |r| {
+        let texture = r.create_texture(&checker);
+        r.draw_image(
+            texture,
+            Rectangle::new(-0.75, -0.75, 1.5, 1.5).color(RGBA8::new(255, 255, 255, 255)),
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn blending() {
+    check("blending", Tolerance::default(), |r| {
+        r.draw_shape(&Rectangle::new(-0.75, -0.75, 1.0, 1.0).color(RGBA8::new(255, 0, 0, 255)));
+        r.draw_shape(&Rectangle::new(-0.25, -0.25, 1.0, 1.0).color(RGBA8::new(0, 0, 255, 128)));
+    });
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn blend_modes() {
+    let modes = [
+        BlendMode::Alpha,
+        BlendMode::Premultiplied,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Replace,
+    ];
+    // One column per mode over a red top half, on a 96x32 target. The
+    // premultiplied column gets the same color with its alpha applied.
+    check_sized("blend_modes", (96, 32), Tolerance::default(), |r| {
+        r.draw_shape(&Rectangle::new(-1.0, -1.0, 2.0, 1.0).color(RGBA8::new(255, 0, 0, 255)));
+        for (i, &mode) in modes.iter().enumerate() {
+            r.set_blend_mode(mode);
+            let x = (i as f32 * 16.0 + 2.0) / 48.0 - 1.0;
+            let color = match mode {
+                BlendMode::Premultiplied => RGBA8::new(0, 64, 128, 128),
+                _ => RGBA8::new(0, 128, 255, 128),
+            };
+            r.draw_shape(&Rectangle::new(x, -1.0, 0.25, 2.0).color(color));
+        }
+    });
+}
+
+#[test]
+#[ignore = "needs a Vulkan adapter"]
+fn draw_uniforms() {
+    // The same white rectangle twice, moved and tinted by its draw's block.
+    let image = render(SIZE, SIZE, |r| {
+        let rect = Rectangle::new(-1.0, -1.0, 1.0, 2.0).color(RGBA8::new(255, 255, 255, 255));
+        r.set_draw_uniforms(DrawUniforms {
+            tint: [1.0, 0.0, 0.0, 1.0],
+            ..DrawUniforms::default()
+        });
+        r.draw_shape(&rect);
+        r.set_draw_uniforms(DrawUniforms {
+            transform: Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0)),
+            tint: [0.0, 0.0, 1.0, 1.0],
+            time: 0.0,
+        });
+        r.draw_shape(&rect);
+    });
+
+    assert_eq!(Rgba([255, 0, 0, 255]), *image.get_pixel(SIZE / 4, SIZE / 2));
+    assert_eq!(Rgba([0, 0, 255, 255]), *image.get_pixel(SIZE * 3 / 4, SIZE / 2));
+}
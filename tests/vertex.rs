@@ -0,0 +1,53 @@
+use cgmath::{Vector2, Vector3};
+use rgb::RGBA8;
+
+use core::vertex::{Format, VertexLayout};
+
+#[repr(C)]
+#[derive(Clone, Copy, VertexLayout)]
+#[vertex(crate = "::core")]
+pub struct Particle {
+    position: Vector3<f32>,
+    /// Not read by the shader.
+    #[vertex(skip)]
+    _pad: f32,
+    velocity: [f32; 2],
+    color: RGBA8,
+    #[vertex(format = "Rg16Unorm")]
+    uv: [u16; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, VertexLayout)]
+#[vertex(crate = "::core")]
+struct Tuple(Vector2<f32>, u32);
+
+fn layout<V: VertexLayout>(binding: u32) -> Vec<(u32, u32, Format, u32)> {
+    V::attributes(binding)
+        .iter()
+        .map(|a| (a.location, a.binding, a.element.format, a.element.offset))
+        .collect()
+}
+
+#[test]
+fn named_fields() {
+    assert_eq!(
+        vec![
+            (0, 3, Format::Rgb32Sfloat, 0),
+            (1, 3, Format::Rg32Sfloat, 16),
+            (2, 3, Format::Rgba8Unorm, 24),
+            (3, 3, Format::Rg16Unorm, 28),
+        ],
+        layout::<Particle>(3)
+    );
+    assert_eq!(32, Particle::buffer_desc(3).stride);
+}
+
+#[test]
+fn tuple_fields() {
+    assert_eq!(
+        vec![(0, 0, Format::Rg32Sfloat, 0), (1, 0, Format::R32Uint, 8)],
+        layout::<Tuple>(0)
+    );
+    assert_eq!(12, Tuple::buffer_desc(0).stride);
+}